@@ -0,0 +1,129 @@
+// Where the cycles of z^d + c collide: the c for which the dynatomic
+// polynomial Phi_n(x, c) has a repeated root in x, that is the roots
+// of its discriminant D_n(c). These are where an n-cycle is born (with
+// multiplier 1) or splits off a cycle of length n/k (whose multiplier
+// is a primitive k-th root of unity), so candidates sitting on them
+// are worth a second look. The coefficients of D_n are far too big for
+// i64, so it's computed mod a few large primes, by evaluating the
+// discriminant in x at enough values of c and interpolating
+
+use crate::fp_poly::FpPoly;
+use crate::math::*;
+use crate::util::Rational;
+
+// The primes D_n is computed mod. A rational c is only flagged when
+// D_n(c) vanishes mod all of them (those not dividing its denominator)
+pub const BIFURCATION_PRIMES: [i64; 3] = [1_000_000_007, 1_000_000_009, 998_244_353];
+
+// Phi_n of z^d + c over F_p
+pub fn unicritical_dynatomic(d: usize, n: usize, c: u64, m: Modulus) -> FpPoly {
+    let mut coeffs = vec![0; d + 1];
+    coeffs[0] = c;
+    coeffs[d] = 1;
+    FpPoly::new(coeffs, m).dynatomic(n)
+}
+
+// The degree in x of Phi_n for z^d + c (or any polynomial of degree d)
+pub fn dynatomic_degree(d: usize, n: usize) -> usize {
+    let total: i64 = (1..=n)
+        .filter(|k| n.is_multiple_of(*k))
+        .map(|k| mobius(n / k) as i64 * (d as i64).pow(k as u32))
+        .sum();
+    total as usize
+}
+
+// D_n mod p as a polynomial in c. Giving x weight 1 and c weight d,
+// Phi_n has weighted degree deg Phi_n = D, and its discriminant is
+// isobaric of weight D(D - 1), so D_n has degree at most D(D - 1)/d
+pub fn dynatomic_discriminant(d: usize, n: usize, p: i64) -> FpPoly {
+    let m = Modulus::new(p);
+    let degree = dynatomic_degree(d, n);
+    let points = degree * (degree - 1) / d + 1;
+    assert!((points as i64) < p, "p too small to interpolate D_n");
+    let values: Vec<u64> = (0..points as u64)
+        .map(|c| unicritical_dynatomic(d, n, c, m).discriminant())
+        .collect();
+    interpolate(&values, m)
+}
+
+// The polynomial of degree < values.len() taking values[i] at i, by
+// Newton's divided differences (the nodes are 0, 1, ..., so the
+// differences j apart are all divided by j)
+fn interpolate(values: &[u64], m: Modulus) -> FpPoly {
+    let n = values.len();
+    let mut a = values.to_vec();
+    for j in 1..n {
+        let inv = m.inverse(j as u64).unwrap();
+        for i in (j..n).rev() {
+            a[i] = m.mul(m.sub(a[i], a[i - 1]), inv);
+        }
+    }
+    // a[0] + a[1] x + a[2] x (x - 1) + ..., by Horner's rule
+    let mut res: Vec<u64> = vec![a[n - 1]];
+    for k in (0..n - 1).rev() {
+        // res * (x - k) + a[k]
+        let mut next = vec![0; res.len() + 1];
+        for (i, &r) in res.iter().enumerate() {
+            next[i + 1] = m.add(next[i + 1], r);
+            next[i] = m.sub(next[i], m.mul(k as u64, r));
+        }
+        next[0] = m.add(next[0], a[k]);
+        res = next;
+    }
+    FpPoly::new(res, m)
+}
+
+pub struct BifurcationLocus {
+    // (n, D_n mod each of BIFURCATION_PRIMES) for n = 1, 2, ...
+    discriminants: Vec<(usize, Vec<FpPoly>)>,
+}
+
+#[allow(unused)]
+impl BifurcationLocus {
+    // D_1, ..., D_max_period for z^d + c
+    pub fn new(d: usize, max_period: usize) -> BifurcationLocus {
+        let discriminants = (1..=max_period)
+            .map(|n| (n, BIFURCATION_PRIMES.iter().map(|&p| dynatomic_discriminant(d, n, p)).collect()))
+            .collect();
+        BifurcationLocus { discriminants }
+    }
+
+    // The n for which c is a root of D_n (mod every prime it reduces mod)
+    pub fn periods_at(&self, c: Rational) -> Vec<usize> {
+        self.discriminants.iter().filter(|(_, polys)| {
+            let mut reduced = polys.iter().filter(|f| c.denom % f.modulus.value() != 0).peekable();
+            reduced.peek().is_some() && reduced.all(|f| f.eval(c.reduce(f.modulus.value() as usize) as u64) == 0)
+        }).map(|&(n, _)| n).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quadratic_bifurcations() {
+        let p = BIFURCATION_PRIMES[0];
+        let m = Modulus::new(p);
+        // Phi_1 = x^2 - x + c and Phi_2 = x^2 + x + c + 1
+        assert_eq!(dynatomic_discriminant(2, 1, p), FpPoly::new(vec![1, m.reduce(-4)], m));
+        assert_eq!(dynatomic_discriminant(2, 2, p), FpPoly::new(vec![m.reduce(-3), m.reduce(-4)], m));
+        assert_eq!(dynatomic_degree(4, 3), 60);
+
+        // The interpolation has enough points: D_3 of z^4 + c agrees
+        // with the discriminant at values of c past the nodes
+        let d3 = dynatomic_discriminant(4, 3, p);
+        for &c in &[5_000u64, 123_456_789, p as u64 - 1] {
+            assert_eq!(d3.eval(c), unicritical_dynatomic(4, 3, c, m).discriminant());
+        }
+
+        let locus = BifurcationLocus::new(2, 4);
+        // Fixed points collide at 1/4, the fixed point doubles at -3/4,
+        // the 2-cycle doubles at -5/4 and 3-cycles are born at -7/4
+        assert_eq!(locus.periods_at(Rational::new(1, 4)), vec![1]);
+        assert_eq!(locus.periods_at(Rational::new(-3, 4)), vec![2]);
+        assert!(locus.periods_at(Rational::new(-5, 4)).contains(&4));
+        assert!(locus.periods_at(Rational::new(-7, 4)).contains(&3));
+        assert!(locus.periods_at(Rational::new(-29, 16)).is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+// Joint configurations of rational cycles: a multiset of cycle lengths,
+// like "a fixed point and two 3-cycles", asked about all at once. The
+// reduction mod p of each rational cycle is a cycle mod p allowing its
+// length (m, m r or m r p^e, as in fast_possible_periods), and several
+// rational cycles can only share a cycle mod p in a limited way: in
+// the residue discs of an m-cycle whose multiplier isn't 1 there is
+// exactly one point of period m, so it carries at most one rational
+// m-cycle, though any number of the longer m r and m r p^e ones (a
+// rational fixed point with multiplier -1 mod p and a rational 2-cycle
+// can sit over the same fixed point mod p, say). A configuration is
+// admissible at p when its cycles can be placed this way
+
+use crate::cycle_structure::CycleStructure;
+use crate::period_set::PeriodSet;
+use crate::prime_context::PrimeContext;
+use crate::util::*;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Configuration {
+    // The cycle lengths, in increasing order (with repeats)
+    lengths: Vec<usize>,
+}
+
+#[allow(unused)]
+impl Configuration {
+    pub fn new(mut lengths: Vec<usize>) -> Configuration {
+        lengths.sort_unstable();
+        Configuration { lengths }
+    }
+
+    // The distinct lengths, each with how many cycles have it
+    pub fn counts(&self) -> Vec<(usize, usize)> {
+        let mut res: Vec<(usize, usize)> = Vec::new();
+        for &n in &self.lengths {
+            match res.last_mut() {
+                Some((m, k)) if *m == n => *k += 1,
+                _ => res.push((n, 1)),
+            }
+        }
+        res
+    }
+
+    pub fn longest(&self) -> usize {
+        self.lengths.last().copied().unwrap_or(0)
+    }
+
+    pub fn periods(&self) -> PeriodSet {
+        self.lengths.iter().copied().collect()
+    }
+
+    // Does a set of possible periods (one prime's, or an intersection
+    // of them) contain every length? This is all the period tables
+    // can say; the multiplicities need admits
+    pub fn allowed_by(&self, periods: &PeriodSet) -> bool {
+        self.lengths.iter().all(|&n| periods.contains(n))
+    }
+
+    // Can the cycles mod p carry the configuration? Cycles of
+    // different lengths never compete for a cycle mod p (only an
+    // n-cycle mod p is limited to one rational n-cycle), so this is
+    // a count for each length
+    pub fn admits(&self, s: &CycleStructure) -> bool {
+        self.counts().into_iter().all(|(n, k)| {
+            let allowing = s.cycles_allowing(n, 1);
+            let exclusive = allowing.iter().filter(|c| c.length == n && c.multiplier != 1).count();
+            exclusive < allowing.len() || k <= exclusive
+        })
+    }
+}
+
+// "2,3,3", say
+impl FromStr for Configuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Configuration, String> {
+        let lengths = s.split(',')
+            .map(|n| match n.trim().parse::<usize>() {
+                Ok(0) => Err("cycle lengths are positive".to_string()),
+                Ok(n) => Ok(n),
+                Err(e) => Err(format!("{}: {}", n.trim(), e)),
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+        Ok(Configuration::new(lengths))
+    }
+}
+
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.counts().iter().map(|&(n, k)| {
+            if k == 1 { format!("a {}-cycle", n) } else { format!("{} {}-cycles", k, n) }
+        }).collect();
+        write!(f, "{}", parts.join(" and "))
+    }
+}
+
+// Could z^4 + c have config's rational cycles all at once, as far as
+// the primes of the period tables can tell? The table rows rule out
+// most c; the cycle structures are only needed for the survivors of a
+// configuration with repeated lengths
+pub fn z4c_admits(c: Rational, config: &Configuration, contexts: &[PrimeContext]) -> bool {
+    let good = || contexts.iter().take_while(|ctx| !ctx.z4_table.is_empty())
+        .filter_map(move |ctx| ctx.reduce_rational(c).map(|r| (ctx, r)));
+    if !good().all(|(ctx, r)| config.allowed_by(&ctx.z4_table[r])) {
+        return false;
+    }
+    if config.counts().iter().all(|&(_, k)| k == 1) {
+        return true;
+    }
+    good().all(|(ctx, r)| {
+        let f = Polynomial::new(vec![1, 0, 0, 0, r as i64], Some(ctx.p as i64));
+        config.admits(&CycleStructure::new(&f))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prime_context::prime_contexts;
+
+    #[test]
+    fn configurations() {
+        let config: Configuration = "3, 1,3".parse().unwrap();
+        assert_eq!(config.counts(), vec![(1, 1), (3, 2)]);
+        assert_eq!(format!("{}", config), "a 1-cycle and 2 3-cycles");
+        assert!("2,0".parse::<Configuration>().is_err());
+
+        // z^2 mod 7: fixed points 0 (multiplier 0) and 1 (multiplier 2,
+        // of order 3) and the 2-cycle 2 <-> 4 (multiplier 4, of order 3)
+        let s = CycleStructure::new(&Polynomial::new(vec![1, 0, 0], Some(7)));
+        let admits = |c: &str| c.parse::<Configuration>().unwrap().admits(&s);
+        assert!(admits("1,1") && !admits("1,1,1"));
+        assert!(admits("1,2") && !admits("2,2"));
+        // 3 = 1 * 3 and 6 = 2 * 3 can share their cycles
+        assert!(admits("3,3,3") && admits("1,1,2,3,6,6"));
+        assert!(!admits("4"));
+
+        // z^4 + c has the fixed point x when c = x - x^4
+        let contexts = prime_contexts();
+        for u in (-9..=9i64).step_by(2) {
+            let c = Rational::new(8 * u - u.pow(4), 16);
+            assert!(z4c_admits(c, &"1".parse().unwrap(), contexts));
+        }
+        assert!(!z4c_admits(Rational::new(1, 16), &"1,1,1,1,1".parse().unwrap(), contexts));
+        // and integers (like -14, fixing 2) only have 1- and 2-cycles
+        assert!(z4c_admits(Rational::new(-14, 1), &"1".parse().unwrap(), contexts));
+        assert_eq!("2,1,2".parse::<Configuration>().unwrap().longest(), 2);
+    }
+}
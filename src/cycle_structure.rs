@@ -0,0 +1,187 @@
+// Everything the walk in for_each_cycle sees of the functional
+// graph of f mod p, rather than just the periods it allows: each cycle
+// with its multiplier and that multiplier's order, how far the other
+// points are from the cycles, and so which cycles allow which periods
+
+use crate::ds_helper::{for_each_cycle, max_period_p_exponent};
+use crate::math::*;
+use crate::period_set::PeriodSet;
+use crate::util::Polynomial;
+use std::fmt;
+
+// Largest p CycleStructure::new takes: unlike for_each_cycle it keeps
+// each point's distance to the cycles, and can list a whole tail, so
+// it needs up to 8p bytes (128 MiB here)
+pub const MAX_STRUCTURE_PRIME: i64 = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleInfo {
+    pub length: usize,
+    // The first point of the cycle the walk reached
+    pub point: i64,
+    pub multiplier: i64,
+    // Order of the multiplier in F_p^* (None when it's 0)
+    pub order: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CycleStructure {
+    pub p: i64,
+    pub cycles: Vec<CycleInfo>,
+    // tails[k] is the number of points k steps from a cycle
+    // (so tails[0] is the number of periodic points)
+    pub tails: Vec<usize>,
+}
+
+#[allow(unused)]
+impl CycleInfo {
+    // The cycle of f mod p (f must be mod a prime) through x, which
+    // must be periodic
+    pub fn through(f: &Polynomial, x: i64) -> CycleInfo {
+        let p = f.p_mod.unwrap();
+        let m = Modulus::new(p);
+        let derivative = f.derivative();
+        let (mut length, mut multiplier, mut y) = (0, 1, x);
+        loop {
+            multiplier = m.mul(multiplier, derivative.eval(y) as u64);
+            length += 1;
+            y = f.eval(y);
+            if y == x {
+                break;
+            }
+        }
+        let multiplier = multiplier as i64;
+        let order = (multiplier != 0).then(|| multiplicative_order(multiplier, p));
+        CycleInfo { length, point: x, multiplier, order }
+    }
+
+    // The periods over Q this cycle allows: m, m r and m r p^e
+    // (with e up to max_e)
+    pub fn allowed_periods(&self, p: i64, max_e: u32) -> Vec<usize> {
+        let mut res = vec![self.length];
+        if let Some(r) = self.order {
+            let mut pe = 1;
+            for _ in 0..=max_e {
+                res.push(self.length * r as usize * pe);
+                pe *= p as usize;
+            }
+        }
+        res
+    }
+}
+
+#[allow(unused)]
+impl CycleStructure {
+    // Walk the functional graph of f mod p (f must be mod a prime, at
+    // most MAX_STRUCTURE_PRIME)
+    pub fn new(f: &Polynomial) -> CycleStructure {
+        let p = f.p_mod.unwrap();
+        assert!(p <= MAX_STRUCTURE_PRIME, "p too large for CycleStructure::new");
+        let next = |x: u32| f.eval(x as i64) as u32;
+        let mut cycles = Vec::new();
+        for_each_cycle(f, |cycle| cycles.push(cycle));
+
+        // Distances to the cycles, filled in back along each walk
+        let mut depth = vec![u32::MAX; p as usize];
+        for c in &cycles {
+            let mut y = c.point as u32;
+            for _ in 0..c.length {
+                depth[y as usize] = 0;
+                y = next(y);
+            }
+        }
+        let mut walk: Vec<u32> = Vec::new();
+        for start in 0..p as u32 {
+            let mut x = start;
+            while depth[x as usize] == u32::MAX {
+                walk.push(x);
+                x = next(x);
+            }
+            let mut d = depth[x as usize];
+            while let Some(y) = walk.pop() {
+                d += 1;
+                depth[y as usize] = d;
+            }
+        }
+        let mut tails = vec![0; depth.iter().max().map_or(0, |&d| d as usize + 1)];
+        for &d in &depth {
+            tails[d as usize] += 1;
+        }
+
+        CycleStructure { p, cycles, tails }
+    }
+
+    // Each component of a functional graph has exactly one cycle
+    pub fn components(&self) -> usize {
+        self.cycles.len()
+    }
+
+    pub fn periodic_points(&self) -> usize {
+        self.tails.first().copied().unwrap_or(0)
+    }
+
+    // The longest tail
+    pub fn max_tail(&self) -> usize {
+        self.tails.len().saturating_sub(1)
+    }
+
+    // As fast_possible_periods_ramified
+    pub fn possible_periods(&self, ramification: u32) -> PeriodSet {
+        let max_e = max_period_p_exponent(self.p as usize, ramification);
+        self.cycles.iter().flat_map(|c| c.allowed_periods(self.p, max_e)).collect()
+    }
+
+    // The cycles which allow a period of n over Q (none, if it's ruled out)
+    pub fn cycles_allowing(&self, n: usize, ramification: u32) -> Vec<&CycleInfo> {
+        let max_e = max_period_p_exponent(self.p as usize, ramification);
+        self.cycles.iter().filter(|c| c.allowed_periods(self.p, max_e).contains(&n)).collect()
+    }
+}
+
+impl fmt::Display for CycleStructure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "mod {}: {} cycles, {} periodic points, tails up to {} long {:?}",
+                 self.p, self.components(), self.periodic_points(), self.max_tail(), self.tails)?;
+        for c in &self.cycles {
+            match c.order {
+                Some(r) => writeln!(f, "  {}-cycle through {}, multiplier {} of order {}", c.length, c.point, c.multiplier, r)?,
+                None => writeln!(f, "  {}-cycle through {}, multiplier 0", c.length, c.point)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ds_helper::fast_possible_periods_ramified;
+
+    #[test]
+    fn structure_of_squaring() {
+        // z^2 mod 7: 0 and 1 are fixed, 2 <-> 4, and 3, 5, 6 map onto them
+        let s = CycleStructure::new(&Polynomial::new(vec![1, 0, 0], Some(7)));
+        assert_eq!(s.cycles, vec![
+            CycleInfo { length: 1, point: 0, multiplier: 0, order: None },
+            CycleInfo { length: 1, point: 1, multiplier: 2, order: Some(3) },
+            CycleInfo { length: 2, point: 2, multiplier: 4, order: Some(3) },
+        ]);
+        assert_eq!(s.tails, vec![4, 3]);
+        assert_eq!((s.components(), s.max_tail()), (3, 1));
+        // 6 = 2 * 3 comes from the 2-cycle, 3 from the fixed point 1
+        assert_eq!(s.cycles_allowing(6, 1), vec![&s.cycles[2]]);
+        assert_eq!(s.cycles_allowing(3, 1), vec![&s.cycles[1]]);
+        assert!(s.cycles_allowing(5, 1).is_empty());
+
+        for &p in &[2, 3, 13, 97, 211] {
+            for c in 0..10 {
+                let f = || Polynomial::new(vec![1, 0, 0, 0, c], Some(p));
+                let s = CycleStructure::new(&f());
+                assert_eq!(s.tails.iter().sum::<usize>(), p as usize);
+                for ramification in 1..=2 {
+                    assert_eq!(s.possible_periods(ramification), fast_possible_periods_ramified(f(), ramification));
+                }
+            }
+        }
+    }
+}
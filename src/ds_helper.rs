@@ -1,109 +1,267 @@
 
 use crate::util::*;
-use std::collections::{HashSet, BTreeMap};
-use std::hash::BuildHasherDefault;
-use fnv::FnvHasher;
-use arrayvec::ArrayVec;
 use crate::math::*;
-
-type FNVHashSet = HashSet<usize, BuildHasherDefault<FnvHasher>>;
+use crate::period_set::PeriodSet;
+use crate::period_table::*;
+use crate::fp_poly::FpPoly;
+use crate::prime_context::PrimeContext;
+use crate::bifurcation::dynatomic_degree;
+use crate::cycle_structure::CycleInfo;
+use num_integer::Integer;
 
 // In general: for a polynomial in Q, find the possible periods
 // greater than goal
 #[allow(unused)]
-pub fn possible_periods_search(f: PolynomialInQ, goal: usize) -> Option<FNVHashSet> {
-    let mut res = FNVHashSet::default();
+pub fn possible_periods_search(f: PolynomialInQ, goal: usize) -> Option<PeriodSet> {
+    let mut res = PeriodSet::new();
     let mut first = true;
     for p in 2..=100 {
-        if prime(p) && f.has_good_reduction(p) {
-            if first {
-                res = fast_possible_periods(f.do_reduction(p));
-                first = false;
-            } else {
-                let pers = fast_possible_periods(f.do_reduction(p));
-                res = res.intersection(&pers).map(|&x| x).collect();
-            }
-            // Check if our set contains anything
-            // large enough to be interesting
-            let mut found = false;
-            for possible in &res {
-                if *possible > goal {
-                    found = true;
-                    break;
-                }
-            }
-            if !found {
-                return None;
-            }
+        if !prime(p) {
+            continue;
+        }
+        // If f itself has bad reduction, a conjugate might not
+        let red = if f.has_good_reduction(p) {
+            f.do_reduction(p)
+        } else if let Some((g, _, _)) = f.good_reduction_model(p) {
+            g.do_reduction(p)
+        } else {
+            continue;
+        };
+        if first {
+            res = fast_possible_periods(red);
+            first = false;
+        } else {
+            res.intersect_with(&fast_possible_periods(red));
+        }
+        // Check if our set contains anything
+        // large enough to be interesting
+        if !res.any_above(goal) {
+            return None;
         }
     }
 
     // Remove everything not in the goal
-    let not_interesting: FNVHashSet = (0..=goal).collect();
-    res = res.difference(&not_interesting).map(|&x| x).collect();
+    res.remove_up_to(goal);
 
     Some(res)
 }
 
+// As possible_periods_search, then also using a few huge primes
+// (which only pay off for the odd stubborn candidate)
+#[allow(unused)]
+pub fn possible_periods_search_with_primes(f: PolynomialInQ, goal: usize, huge_primes: &[i64]) -> Option<PeriodSet> {
+    let res = possible_periods_search(f.clone(), goal)?;
+    let res = huge_prime_refinement(&f, &res, huge_primes);
+    if res.is_empty() { None } else { Some(res) }
+}
+
+// Largest Phi_n huge_prime_refinement builds (dynatomic composes
+// schoolbook, so degree d^n soon gets out of hand)
+const MAX_HUGE_PRIME_DEGREE: usize = 1024;
+
+// Intersect candidates with what the reductions of f at some huge
+// primes allow, testing each candidate n < p for points of formal
+// period n by a polynomial gcd instead of walking all of F_p. The
+// candidates n >= p (which might be m r p^e) are kept, as are those
+// whose Phi_n is too large to build and everything at the primes
+// where f has bad reduction
+pub fn huge_prime_refinement(f: &PolynomialInQ, candidates: &PeriodSet, primes: &[i64]) -> PeriodSet {
+    let d = f.degree();
+    let testable = |n: usize| {
+        n < 64 && (d as i64).checked_pow(n as u32).is_some()
+            && dynatomic_degree(d, n) <= MAX_HUGE_PRIME_DEGREE
+    };
+    let mut res = candidates.clone();
+    for &p in primes {
+        if res.is_empty() {
+            break;
+        }
+        if !f.has_good_reduction(p as usize) {
+            continue;
+        }
+        let red = FpPoly::from_polynomial(&f.do_reduction(p as usize));
+        res = res.iter().filter(|&n| n as i64 >= p || !testable(n) || red.has_formal_period(n)).collect();
+    }
+    res
+}
+
 // Find the possible periods of a polynomial
 // in Q(w)
-pub fn possible_periods_search_qw(f: PolynomialInQw, goal: usize) -> Option<FNVHashSet> {
-    let mut res = FNVHashSet::default();
+#[allow(unused)]
+pub fn possible_periods_search_qw(f: PolynomialInQw, goal: usize, contexts: &[PrimeContext]) -> Option<PeriodSet> {
+    let mut res = PeriodSet::new();
     let mut first = true;
-    for p in 2..=300 {
-        if prime(p as usize) && has_qw_homomorphism(p) {
-            if first {
-                let (red1, red2) = f.reductions(p);
-                match (red1, red2) {
-                    (None, None) => continue,
-                    (Some(r1), None) => res = fast_possible_periods(r1),
-                    (None, Some(r2)) => res = fast_possible_periods(r2),
-                    (Some(r1), Some(r2)) => {
-                        res = fast_possible_periods(r1);
-                        res = res.intersection(&fast_possible_periods(r2)).map(|&x| x).collect();
-                    }
-                }
-                first = false;
-            } else {
-                let (red1, red2) = f.reductions(p);
-                if let Some(r1) = red1 {
-                    res = res.intersection(&fast_possible_periods(r1)).map(|&x| x).collect();
-                }
-                if let Some(r2) = red2 {
-                    res = res.intersection(&fast_possible_periods(r2)).map(|&x| x).collect();
-                }
-                // Check if our set contains anything
-                // large enough to be interesting
-                let mut found = false;
-                for possible in &res {
-                    if *possible > goal {
-                        found = true;
-                        break;
-                    }
-                }
-                if !found {
-                    return None;
+    for ctx in contexts {
+        if !ctx.w_images.is_empty() {
+            let p = ctx.p as i64;
+            for (i, red) in ctx.reduce_qw_polynomial(&f).into_iter().enumerate() {
+                // As in possible_periods_search, a conjugate of f might
+                // have good reduction where f doesn't
+                let red = match red {
+                    Some(red) => red,
+                    None => match f.good_reduction_model(p, i) {
+                        Some((g, _, _)) => ctx.reduce_qw_polynomial(&g).swap_remove(i).unwrap(),
+                        None => continue,
+                    },
+                };
+                let pers = fast_possible_periods_ramified(red, qw_ramification(p));
+                if first {
+                    res = pers;
+                    first = false;
+                } else {
+                    res.intersect_with(&pers);
                 }
             }
+            if first {
+                continue;
+            }
+            // Check if our set contains anything
+            // large enough to be interesting
+            if !res.any_above(goal) {
+                return None;
+            }
         }
     }
 
     // Remove everything not in the goal
-    let not_interesting: FNVHashSet = (0..=goal).collect();
-    res = res.difference(&not_interesting).map(|&x| x).collect();
+    res.remove_up_to(goal);
 
     Some(res)
 }
 
-pub fn fast_possible_periods(f: Polynomial) -> FNVHashSet {
+// The ramification index over p of the primes of Z[w]
+// which have F_p as residue field: 3 = -w^2 (1 - w)^2
+pub fn qw_ramification(p: i64) -> u32 {
+    if p == 3 { 2 } else { 1 }
+}
+
+// Morton-Silverman / Zieve: if a point of exact period n over a
+// field with good reduction at a prime P above p reduces to a point
+// of exact period m whose multiplier has order r in F_p^*, then
+// n = m, n = mr, or n = m r p^e where p^(e-1) <= 2 v(p) / (p - 1),
+// v(p) being the ramification index of P over p. This is the
+// largest e the last case allows (0 if it never happens).
+pub fn max_period_p_exponent(p: usize, ramification: u32) -> u32 {
+    let bound = 2 * ramification as usize;
+    let mut e = 0;
+    let mut pe = 1;
+    // p^e * (p - 1) <= 2 v(p) is p^((e + 1) - 1) <= 2 v(p) / (p - 1)
+    while pe * (p - 1) <= bound {
+        e += 1;
+        pe *= p;
+    }
+    e
+}
+
+// Possible periods over Q of the points reducing to the
+// cycles of f, which is the reduction of a polynomial
+// over Q mod p (CycleStructure::new keeps the rest of
+// what the walk finds, to see why)
+pub fn fast_possible_periods(f: Polynomial) -> PeriodSet {
+    fast_possible_periods_ramified(f, 1)
+}
+
+// As fast_possible_periods, for the reduction at a prime
+// with the given ramification index over p
+pub fn fast_possible_periods_ramified(f: Polynomial, ramification: u32) -> PeriodSet {
     let p = f.p_mod.unwrap();
+    let max_e = max_period_p_exponent(p as usize, ramification);
+    let mut periods = PeriodSet::new();
+    for_each_cycle(&f, |cycle| periods.extend(cycle.allowed_periods(p, max_e)));
+    periods
+}
+
+// The cycles of f mod p, each starting from the first point of
+// it reached, using the same walk as fast_possible_periods
+pub fn periodic_cycles(f: &Polynomial) -> Vec<Vec<i64>> {
+    let mut cycles = Vec::new();
+    for_each_cycle(f, |cycle| cycles.push(f.n_orbit(cycle.point, cycle.length)));
+    cycles
+}
 
-    // We don't need the point at infinity, so we
-    // can use an array of size p instead of p^2
-    //    (also, this way, hash_point = id)
+// Largest modulus the walk handles (points fit in a u32)
+pub const MAX_WALK_PRIME: i64 = u32::MAX as i64;
+
+// Longest stretch of a walk for_each_cycle_point keeps a list of
+pub const MAX_WALK_RECORD: usize = 1 << 16;
+
+// The walk behind fast_possible_periods and CycleStructure: visit each
+// cycle of f mod p (which must be prime) with its multiplier and order
+pub fn for_each_cycle<F>(f: &Polynomial, mut visit: F)
+    where F: FnMut(CycleInfo)
+{
+    for_each_cycle_point(f, |x| visit(CycleInfo::through(f, x)));
+}
+
+// Call visit on one point of each cycle of f mod q (any modulus), the
+// first the walk reached, in q/4 bytes plus at most 4 MAX_WALK_RECORD:
+// one bitmap of the points seen by any walk and one of those on the
+// current walk, cleared as it ends. A walk stops at the first point
+// seen before, which starts a new cycle exactly when it's on the
+// current walk. The walk is also listed (to clear its bits), up to
+// MAX_WALK_RECORD points; past that, the points to clear are found by
+// walking again
+pub fn for_each_cycle_point<F>(f: &Polynomial, mut visit: F)
+    where F: FnMut(i64)
+{
+    let q = f.p_mod.unwrap();
+    assert!(q <= MAX_WALK_PRIME, "modulus too large for for_each_cycle_point");
+    let next = |x: u32| f.eval(x as i64) as u32;
+
+    let words = (q as usize).div_ceil(64);
+    let mut seen = vec![0u64; words];
+    let mut on_walk = vec![0u64; words];
+    let mut walk: Vec<u32> = Vec::new();
+
+    for start in 0..q as u32 {
+        if bit(&seen, start) {
+            continue;
+        }
+        let mut x = start;
+        let mut recorded = true;
+        while !bit(&seen, x) {
+            set_bit(&mut seen, x);
+            set_bit(&mut on_walk, x);
+            if walk.len() < MAX_WALK_RECORD {
+                walk.push(x);
+            } else {
+                recorded = false;
+            }
+            x = next(x);
+        }
+
+        if bit(&on_walk, x) {
+            visit(x as i64);
+        }
+
+        if recorded {
+            for &y in &walk {
+                clear_bit(&mut on_walk, y);
+            }
+        } else {
+            // Every point on the walk is marked, up to where it ran
+            // into a cleared point (or one from an earlier walk)
+            let mut y = start;
+            while bit(&on_walk, y) {
+                clear_bit(&mut on_walk, y);
+                y = next(y);
+            }
+        }
+        walk.clear();
+    }
+}
+
+// The walk for_each_cycle_point replaced, kept to check and benchmark
+// it against: a (successor, index) entry for every point mod p, 16p
+// bytes, numbering the points as they're reached so that a walk which
+// ends on a point numbered since it started has found a new cycle
+#[cfg(test)]
+pub fn table_possible_periods(f: &Polynomial, ramification: u32) -> PeriodSet {
+    let p = f.p_mod.unwrap();
+    let max_e = max_period_p_exponent(p as usize, ramification);
     let mut point_table = vec![(0, 0); p as usize];
     let mut index = 1;
-    let mut periods = FNVHashSet::default();
+    let mut periods = PeriodSet::new();
 
     for p_start in 0..p {
         let mut P = p_start;
@@ -121,20 +279,7 @@ pub fn fast_possible_periods(f: Polynomial) -> FNVHashSet {
             }
 
             if point_table[hash_p].1 >= startindex {
-                let period = index - point_table[hash_p].1;
-                periods.insert(period);
-                let charpoly_constant = f.multiplier(period, P);
-                if charpoly_constant == 0 {
-                    continue; // Exclude 0
-                }
-                // lrorder is both lorder and rorder from sage
-                let lrorder = multiplicative_order(charpoly_constant, p);
-                
-                let r = lrorder as usize;
-                periods.insert(period * r);
-                if p == 2 || p == 3 { // Over Q or Q(w), we need to consider e=1 for p=2 and p=3
-                    periods.insert(period * r * (p as usize));
-                }
+                periods.extend(CycleInfo::through(f, P).allowed_periods(p, max_e));
             }
         }
     }
@@ -142,141 +287,362 @@ pub fn fast_possible_periods(f: Polynomial) -> FNVHashSet {
     periods
 }
 
-pub fn z4c_possible_periods_search(c: Rational, goal: usize) -> Option<FNVHashSet> {
-    let mut res = FNVHashSet::default();
+#[inline]
+fn bit(bits: &[u64], x: u32) -> bool {
+    bits[x as usize / 64] & (1 << (x % 64)) != 0
+}
+
+#[inline]
+fn set_bit(bits: &mut [u64], x: u32) {
+    bits[x as usize / 64] |= 1 << (x % 64);
+}
+
+#[inline]
+fn clear_bit(bits: &mut [u64], x: u32) {
+    bits[x as usize / 64] &= !(1 << (x % 64));
+}
+
+// Possible periods of the points of a polynomial over Q reducing to
+// the cycles of f, its reduction mod p^k (k >= 1). A point of exact
+// period n reducing into a cycle of length L mod p^k has L | n, and n
+// is one of m, mr, m r p^e for the cycle of length m mod p below it,
+// so this refines fast_possible_periods_ramified.
+pub fn fast_possible_periods_prime_power(f: Polynomial, p: i64, ramification: u32) -> PeriodSet {
+    let max_e = max_period_p_exponent(p as usize, ramification);
+    let f_p = Polynomial::new(f.coeffs.iter().map(|c| c.rem_euclid(p)).collect(), Some(p));
+
+    // The same walk as fast_possible_periods_ramified, over Z/p^k
+    let mut periods = PeriodSet::new();
+    for_each_cycle_point(&f, |x| {
+        let mut length = 1;
+        let mut y = f.eval(x);
+        while y != x {
+            y = f.eval(y);
+            length += 1;
+        }
+        // The cycle mod p below this one
+        let below = CycleInfo::through(&f_p, x % p);
+        for n in below.allowed_periods(p, max_e) {
+            if n.is_multiple_of(length) {
+                periods.insert(n);
+            }
+        }
+    });
+
+    periods
+}
+
+// Largest p^k used by z4c_prime_power_refinement
+const MAX_PRIME_POWER: usize = 1 << 16;
+
+// Intersect candidates (from z4c_possible_periods_search, say) with
+// the possible periods of z^4 + c mod p^k, for the primes of good
+// reduction with p^k <= MAX_PRIME_POWER
+pub fn z4c_prime_power_refinement(c: Rational, candidates: &PeriodSet, k: u32) -> PeriodSet {
+    let mut res = candidates.clone();
+    for p in 2..=100usize {
+        if res.is_empty() {
+            break;
+        }
+        if !prime(p) || c.denom % p as i64 == 0 || p.pow(k) > MAX_PRIME_POWER {
+            continue;
+        }
+        let q = p.pow(k);
+        let f = Polynomial::new(vec![1, 0, 0, 0, c.reduce(q) as i64], Some(q as i64));
+        let pers = fast_possible_periods_prime_power(f, p as i64, 1);
+        res.intersect_with(&pers);
+    }
+    res
+}
+
+#[allow(unused)]
+pub fn z4c_possible_periods_search(c: Rational, goal: usize, contexts: &[PrimeContext]) -> Option<PeriodSet> {
+    let mut res = PeriodSet::new();
     let mut first = true;
-    for p in 2..=100 {
-        if prime(p) && c.denom % p as i64 != 0 {
+    for ctx in contexts.iter().take_while(|ctx| !ctx.z4_table.is_empty()) {
+        if let Some(r) = ctx.reduce_rational(c) {
             if first {
-                res = z4_table_possible_periods(p, c.reduce(p)).clone();
+                res = ctx.z4_table[r].clone();
                 first = false;
             } else {
-                let pers = z4_table_possible_periods(p, c.reduce(p));
-                res = res.intersection(&pers).map(|&x| x).collect();
+                res.intersect_with(&ctx.z4_table[r]);
             }
             // Check if our set contains anything
             // large enough to be interesting
-            let mut found = false;
-            for possible in &res {
-                if *possible > goal {
-                    found = true;
-                    break;
-                }
-            }
-            if !found {
+            if !res.any_above(goal) {
                 return None;
             }
         }
     }
 
     // Remove everything not in the goal
-    let not_interesting: FNVHashSet = (0..=goal).collect();
-    res = res.difference(&not_interesting).map(|&x| x).collect();
+    res.remove_up_to(goal);
 
-    Some(res.clone())
+    Some(res)
 }
 
-pub fn z3c_possible_periods_search(c: QwElement, goal: usize) -> Option<FNVHashSet> {
-    let mut res = FNVHashSet::default();
-    let mut first = true;
-    for p in 2..=100 {
-        if prime(p) && has_qw_homomorphism(p as i64) {
-            if first {
-                let (red1, red2) = c.reductions(p as i64);
-                match (red1, red2) {
-                    (None, None) => continue,
-                    (Some(r1), None) => res = z3_table_possible_periods(p, r1 as usize).clone(),
-                    (None, Some(r2)) => res = z3_table_possible_periods(p, r2 as usize).clone(),
-                    (Some(r1), Some(r2)) => {
-                        res = z3_table_possible_periods(p, r1 as usize).clone();
-                        res = res.intersection(&z3_table_possible_periods(p, r2 as usize)).map(|&x| x).collect();
-                    }
-                }
-                first = false;
-            } else {
-                let (red1, red2) = c.reductions(p as i64);
-                if let Some(r1) = red1 {
-                    res = res.intersection(&z3_table_possible_periods(p, r1 as usize)).map(|&x| x).collect();
-                }
-                if let Some(r2) = red2 {
-                    res = res.intersection(&z3_table_possible_periods(p, r2 as usize)).map(|&x| x).collect();
-                }
-                // Check if our set contains anything
-                // large enough to be interesting
-                let mut found = false;
-                for possible in &res {
-                    if *possible > goal {
-                        found = true;
-                        break;
+// Parameters handled together by z4c_possible_periods_batch
+pub const Z4_BATCH_SIZE: usize = 1024;
+
+// z4c_possible_periods_search for a block of parameters at once,
+// returning the index in cs and the possible periods of each one
+// which survives. At each prime, the parameters still alive are
+// reduced together, from separate arrays of numerators and
+// denominators, then all looked up in the table, and the ones with
+// nothing left above goal are dropped, so the work per prime shrinks
+// as we go
+pub fn z4c_possible_periods_batch(cs: &[Rational], goal: usize, contexts: &[PrimeContext]) -> Vec<(usize, PeriodSet)> {
+    // The parameters still alive, as struct-of-arrays (what's known
+    // about each stays where it is, indexed by its place in cs)
+    let mut index: Vec<usize> = (0..cs.len()).collect();
+    let mut numers: Vec<i64> = cs.iter().map(|c| c.numer).collect();
+    let mut denoms: Vec<i64> = cs.iter().map(|c| c.denom).collect();
+    let mut residues: Vec<u64> = vec![0; cs.len()];
+    let mut sets: Vec<BatchSet> = vec![BatchSet::All; cs.len()];
+    let common_denom = match cs.first() {
+        Some(c) if cs.iter().all(|d| d.denom == c.denom) => Some(c.denom),
+        _ => None,
+    };
+
+    for ctx in contexts.iter().take_while(|ctx| !ctx.z4_table.is_empty()) {
+        if index.is_empty() {
+            break;
+        }
+        let m = ctx.modulus;
+        let p = m.value() as u64;
+        // Reduce everything mod p (p marks a bad denominator). The
+        // searches hand over blocks with a single denominator, whose
+        // inverse only needs finding once
+        match common_denom {
+            Some(b) => {
+                let b = m.reduce(b);
+                if b == 0 {
+                    residues.iter_mut().for_each(|r| *r = p);
+                } else {
+                    let inv = ctx.inverses[b as usize];
+                    for (r, &a) in residues.iter_mut().zip(numers.iter()) {
+                        *r = m.mul(m.reduce(a), inv);
                     }
                 }
-                if !found {
-                    return None;
+            }
+            None => {
+                for ((r, &a), &b) in residues.iter_mut().zip(numers.iter()).zip(denoms.iter()) {
+                    let b = m.reduce(b);
+                    *r = if b == 0 { p } else { m.mul(m.reduce(a), ctx.inverses[b as usize]) };
                 }
             }
         }
+        // Look them all up, and keep what's still interesting
+        let mut kept = 0;
+        for i in 0..index.len() {
+            let r = residues[i];
+            if r != p && !sets[index[i]].intersect_with(&ctx.z4_table[r as usize], goal) {
+                continue;
+            }
+            index[kept] = index[i];
+            numers[kept] = numers[i];
+            denoms[kept] = denoms[i];
+            kept += 1;
+        }
+        index.truncate(kept);
+        numers.truncate(kept);
+        denoms.truncate(kept);
+        residues.truncate(kept);
     }
 
-    // Remove everything not in the goal
-    let not_interesting: FNVHashSet = (0..=goal).collect();
-    res = res.difference(&not_interesting).map(|&x| x).collect();
+    index.into_iter().map(|i| {
+        let mut set = match &sets[i] {
+            BatchSet::All => PeriodSet::new(),
+            BatchSet::Table(set) => (*set).clone(),
+            BatchSet::Owned(set) => set.clone(),
+        };
+        // Remove everything not in the goal
+        set.remove_up_to(goal);
+        (i, set)
+    }).collect()
+}
 
-    Some(res)
+// What z4c_possible_periods_batch knows about one parameter: nothing
+// yet, a single row of the table (borrowed, so most parameters, which
+// die after a prime or two, never copy a set), or an intersection
+#[derive(Clone)]
+enum BatchSet {
+    All,
+    Table(&'static PeriodSet),
+    Owned(PeriodSet),
 }
 
-pub fn z4_table_possible_periods(p: usize, c: usize) -> &'static FNVHashSet {
-    &Z4_TABLE[p - 2][c]
+impl BatchSet {
+    // Intersect with a row of the table, and say
+    // whether anything is left above goal
+    fn intersect_with(&mut self, pers: &'static PeriodSet, goal: usize) -> bool {
+        let set = match self {
+            BatchSet::All => {
+                *self = BatchSet::Table(pers);
+                pers
+            }
+            BatchSet::Table(t) => {
+                *self = BatchSet::Owned(t.intersection(pers));
+                match self {
+                    BatchSet::Owned(set) => set,
+                    _ => unreachable!(),
+                }
+            }
+            BatchSet::Owned(set) => {
+                set.intersect_with(pers);
+                set
+            }
+        };
+        set.any_above(goal)
+    }
 }
 
-pub fn z3_table_possible_periods(p: usize, c: usize) -> &'static FNVHashSet {
-    &Z3_TABLE[p - 2][c]
+// Primes of bad reduction for z^d + c, i.e. with v_p(c) < 0.
+//
+// If v(x) < v(c)/d then v(f(x)) = d v(x) < v(x) and the orbit of x
+// escapes; if v(x) > v(c)/d then v(f(x)) = v(c) < v(c)/d. So every
+// periodic point has v(x) = v(c)/d, and there are none unless
+// v(c) = -dt. Then with x = y/p^t and u = c p^(dt) a unit,
+// g(y) = p^(-t(d-1)) (y^d + u) is conjugate to f, and a cycle of g
+// lies in the residue disks around the roots of y^d = -u in F_p.
+// For p not dividing d, g maps each of those k disks onto all of Z_p,
+// expanding distances, so its periodic points over Q_p are those of
+// the full shift on k symbols: none, only fixed points, or every
+// period for k = 0, 1 and >= 2.
+//
+// Returns k, or None if p doesn't divide the denominator of c or if
+// p divides d (where the argument above says nothing).
+pub fn escape_symbols(d: u32, c: Rational, p: usize) -> Option<usize> {
+    let v = c.valuation(p)?;
+    if v >= 0 || (d as usize).is_multiple_of(p) {
+        return None;
+    }
+    if v % d as i32 != 0 {
+        return Some(0);
+    }
+    // -u mod p, where u = c p^(dt) = c / p^v
+    let pk = (p as i64).pow((-v) as u32);
+    let minus_u = Rational::new(-c.numer, c.denom / pk).reduce(p) as i64;
+    // y^d = a has gcd(d, p - 1) roots if a is a (p-1)/g th power root of 1, else none
+    let g = (d as i64).gcd(&(p as i64 - 1));
+    if mod_power(minus_u, (p as i64 - 1) / g, p as i64) == 1 {
+        Some(g as usize)
+    } else {
+        Some(0)
+    }
 }
 
-lazy_static! {
-    static ref Z4_TABLE: Vec<Vec<FNVHashSet>> = {
-        let mut res = Vec::with_capacity(101);
-        for p in 2..=100 {
-            let mut interm = Vec::with_capacity(101);
-            if !prime(p) {
-                res.push(interm);
-                continue;
-            }
-            for c in 0..p {
-                let fc = Polynomial::new(
-                    vec![1, 0, 0, 0, c as i64], Some(p as i64)
-                );
-                let res2 = fast_possible_periods(fc);
-                interm.push(res2);
+// Whether the full shift on k symbols has points of exact period n
+fn full_shift_has_period(k: usize, n: usize) -> bool {
+    match k {
+        0 => false,
+        1 => n == 1,
+        _ => true,
+    }
+}
+
+// Remove the members of candidates that z^d + c can't have as periods
+// because of escape_symbols at the primes in its denominator. Returns
+// the remaining candidates and, for each prime which removed anything,
+// what it removed.
+pub fn bad_prime_filter(d: u32, c: Rational, candidates: &PeriodSet) -> (PeriodSet, Vec<(usize, PeriodSet)>) {
+    let mut res = candidates.clone();
+    let mut report = Vec::new();
+    for (p, _) in factorize(c.denom) {
+        if let Some(k) = escape_symbols(d, c, p as usize) {
+            let (kept, removed): (PeriodSet, PeriodSet) = res.iter().partition(|&n| full_shift_has_period(k, n));
+            if !removed.is_empty() {
+                report.push((p as usize, removed));
             }
-            res.push(interm);
+            res = kept;
         }
-        res
-    };
+    }
+    (res, report)
+}
 
-    static ref Z3_TABLE: Vec<Vec<FNVHashSet>> = {
-        let mut res = Vec::with_capacity(101);
-        for p in 2..=100 {
-            let mut interm = Vec::with_capacity(101);
-            if !prime(p) {
-                res.push(interm);
+pub fn z3c_possible_periods_search(c: QwElement, goal: usize, contexts: &[PrimeContext]) -> Option<PeriodSet> {
+    let mut res = PeriodSet::new();
+    let mut first = true;
+    for ctx in contexts.iter().take_while(|ctx| !ctx.z3_table.is_empty()) {
+        if !ctx.w_images.is_empty() {
+            for r in ctx.reduce_qw(c).flatten() {
+                let pers = &ctx.z3_table[r];
+                if first {
+                    res = pers.clone();
+                    first = false;
+                } else {
+                    res.intersect_with(pers);
+                }
+            }
+            if first {
                 continue;
             }
-            for c in 0..p {
-                let fc = Polynomial::new(
-                    vec![1, 0, 0, c as i64], Some(p as i64)
-                );
-                let res2 = fast_possible_periods(fc);
-                interm.push(res2);
+            // Check if our set contains anything
+            // large enough to be interesting
+            if !res.any_above(goal) {
+                return None;
             }
-            res.push(interm);
         }
-        res
-    };
+    }
+
+    // Remove everything not in the goal
+    res.remove_up_to(goal);
+
+    Some(res)
+}
+
+pub fn z4_table_possible_periods(p: usize, c: usize) -> &'static PeriodSet {
+    Z4_TABLE[p - 2].get(c)
+}
+
+// The table for p, or an empty one if p is past the tables
+pub fn z4_table_row(p: usize) -> &'static PeriodTable {
+    Z4_TABLE.get(p - 2).unwrap_or(&EMPTY_TABLE)
+}
+
+pub fn z3_table_row(p: usize) -> &'static PeriodTable {
+    Z3_TABLE.get(p - 2).unwrap_or(&EMPTY_TABLE)
+}
+
+#[allow(unused)]
+pub fn z3_table_possible_periods(p: usize, c: usize) -> &'static PeriodSet {
+    Z3_TABLE[p - 2].get(c)
+}
+
+// Largest prime with a period table. The tables up to 300 take about
+// 0.1s to build, and the primes past 100 still thin out the candidates
+// of the searches with few periods (like z4c -j 1)
+pub const MAX_TABLE_PRIME: usize = 300;
+
+// The tables for every p up to MAX_TABLE_PRIME (empty for non-primes),
+// one period set per conjugacy class of c
+fn period_tables<F>(d: usize, periods: F) -> Vec<PeriodTable>
+    where F: Fn(usize, usize) -> PeriodSet
+{
+    (2..=MAX_TABLE_PRIME).map(|p| {
+        if !prime(p) {
+            return PeriodTable::default();
+        }
+        PeriodTable::unicritical(p, d, |c| periods(p, c))
+    }).collect()
+}
+
+lazy_static! {
+    static ref Z4_TABLE: Vec<PeriodTable> = period_tables(4, |p, c| {
+        let fc = Polynomial::new(vec![1, 0, 0, 0, c as i64], Some(p as i64));
+        fast_possible_periods(fc)
+    });
+
+    static ref Z3_TABLE: Vec<PeriodTable> = period_tables(3, |p, c| {
+        let fc = Polynomial::new(vec![1, 0, 0, c as i64], Some(p as i64));
+        fast_possible_periods_ramified(fc, qw_ramification(p as i64))
+    });
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::prime_context::prime_contexts;
 
     #[test]
     fn reduction_example() {
@@ -284,8 +650,177 @@ mod tests {
         let den = EisensteinInteger::new(6, 7);
         let nd = QwElement::new(num, den);
 
-        assert_eq!(nd.reductions(7), (Some(2), Some(5)));
+        assert_eq!(nd.reductions(7).as_slice(), &[Some(2), Some(5)]);
 
         println!("{:?}", num.gcd(&den));
+
+        // Only one reduction at the ramified prime above 3
+        assert_eq!(nd.reductions(3).len(), 1);
+        assert!(nd.reductions(5).is_empty());
+    }
+
+    #[test]
+    fn bad_prime_escape() {
+        let all: PeriodSet = (1..=6).collect();
+        // v_3(1/27) isn't a multiple of 2
+        assert_eq!(escape_symbols(2, Rational::new(1, 27), 3), Some(0));
+        // -u = -1 isn't a square mod 3, so z^2 + 1/9 has no 3-adic cycles
+        assert_eq!(escape_symbols(2, Rational::new(1, 9), 3), Some(0));
+        let (kept, report) = bad_prime_filter(2, Rational::new(1, 9), &all);
+        assert!(kept.is_empty());
+        assert_eq!(report, vec![(3, all.clone())]);
+        // z^2 - 7/9 has the 2-cycle -1/3 <-> -2/3, and -u = 7 = 1^2 mod 3
+        assert_eq!(escape_symbols(2, Rational::new(-7, 9), 3), Some(2));
+        assert_eq!(bad_prime_filter(2, Rational::new(-7, 9), &all).0, all);
+        // 2 divides the degree, and 5 isn't in the denominator
+        assert_eq!(escape_symbols(2, Rational::new(-29, 16), 2), None);
+        assert_eq!(escape_symbols(2, Rational::new(-29, 16), 5), None);
+        // x^4 = 1 has 4 roots mod 5 and x^4 = -1 none
+        assert_eq!(escape_symbols(4, Rational::new(-1, 625), 5), Some(4));
+        assert_eq!(escape_symbols(4, Rational::new(1, 625), 5), Some(0));
+    }
+
+    #[test]
+    fn huge_prime_periods() {
+        // z^2 - 29/16 has a 3-cycle, so 3 survives any prime
+        let f = || PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(-29, 16)]);
+        let candidates: PeriodSet = (1..=8).collect();
+        let primes = [1_000_000_007, 998_244_353];
+        let res = huge_prime_refinement(&f(), &candidates, &primes);
+        assert!(res.contains(3) && res.is_subset(&candidates));
+        for n in 1..=8 {
+            assert_eq!(res.contains(n), primes.iter().all(|&q| {
+                FpPoly::from_polynomial(&f().do_reduction(q as usize)).has_formal_period(n)
+            }));
+        }
+        assert!(possible_periods_search_with_primes(f(), 2, &primes).unwrap().contains(3));
+        // Phi_12 of z^4 + c has degree about 4^12, so 12 isn't tested
+        let g = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::zero(), Rational::zero(), Rational::new(-29, 16)]);
+        assert!(huge_prime_refinement(&g, &(12..=12).collect(), &primes).contains(12));
+    }
+
+    #[test]
+    fn walk_periods_match_brute_force() {
+        // The periods allowed by each x that comes back to itself
+        let brute_force = |f: &Polynomial, ramification: u32| {
+            let p = f.p_mod.unwrap();
+            let max_e = max_period_p_exponent(p as usize, ramification);
+            let mut res = PeriodSet::new();
+            for x in 0..p {
+                let orbit = f.n_orbit(x, p as usize + 1);
+                if let Some(n) = (1..=p as usize).find(|&n| orbit[n] == x) {
+                    let multiplier = f.multiplier(n, x);
+                    let order = (multiplier != 0).then(|| multiplicative_order(multiplier, p));
+                    res.extend(CycleInfo { length: n, point: x, multiplier, order }.allowed_periods(p, max_e));
+                }
+            }
+            res
+        };
+        for &p in &[2i64, 3, 7, 13, 31, 97, 211] {
+            for &c in &[0i64, 1, 2, 5, -3, 61] {
+                for &ramification in &[1, 2] {
+                    for d in 3..=4 {
+                        let mut coeffs = vec![0; d + 1];
+                        coeffs[0] = 1;
+                        coeffs[d] = c.rem_euclid(p);
+                        let f = || Polynomial::new(coeffs.clone(), Some(p));
+                        assert_eq!(fast_possible_periods_ramified(f(), ramification), brute_force(&f(), ramification));
+                        assert_eq!(table_possible_periods(&f(), ramification), brute_force(&f(), ramification));
+                    }
+                }
+            }
+        }
+        // x + 1 is a single p-cycle, longer than the walk list
+        let p = 100_003;
+        assert!(p as usize > MAX_WALK_RECORD);
+        assert_eq!(fast_possible_periods(Polynomial::new(vec![1, 1], Some(p))).iter().collect::<Vec<_>>(), vec![p as usize]);
+        // and 2x + 1 has the fixed point -1, and the rest is cycles
+        // of the order of 2, which don't fit the list either
+        let r = multiplicative_order(2, p) as usize;
+        assert!(r > MAX_WALK_RECORD);
+        assert_eq!(fast_possible_periods(Polynomial::new(vec![2, 1], Some(p))).iter().collect::<Vec<_>>(), vec![1, r]);
+    }
+
+    #[test]
+    fn prime_power_periods() {
+        let all: PeriodSet = (1..=30).collect();
+        // k = 1 is the same as working mod p
+        for p in &[2i64, 3, 5, 7, 11] {
+            for c in 0..*p {
+                let f = || Polynomial::new(vec![1, 0, 0, 0, c], Some(*p));
+                assert_eq!(fast_possible_periods_prime_power(f(), *p, 1), fast_possible_periods(f()));
+            }
+        }
+        // Refining never removes a true period: z^2 - 29/16 has a 3-cycle
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(-29, 16)]);
+        for &p in &[3usize, 5, 7, 11, 13] {
+            for k in 1..=3 {
+                let pers = fast_possible_periods_prime_power(f.do_reduction(p.pow(k)), p as i64, 1);
+                assert!(pers.contains(3));
+                assert!(pers.is_subset(&fast_possible_periods(f.do_reduction(p))));
+            }
+        }
+        // ... and it does remove some: mod 5, z^4 + 3 has just the fixed point 4,
+        // with multiplier 1, but mod 25 that point lies on a 5-cycle
+        let f = |q| Polynomial::new(vec![1, 0, 0, 0, 3], Some(q));
+        let mod_p = fast_possible_periods_prime_power(f(5), 5, 1);
+        let mod_p2 = fast_possible_periods_prime_power(f(25), 5, 1);
+        assert_eq!(mod_p, [1].iter().copied().collect());
+        assert!(mod_p2.is_empty());
+        assert!(z4c_prime_power_refinement(Rational::new(3, 1), &all, 2).is_empty());
+    }
+
+    #[test]
+    fn batched_search_matches_single() {
+        let contexts = prime_contexts();
+        for &b in &[16i64, 1296, 10000] {
+            let cs: Vec<Rational> = (-3000..3000i64)
+                .filter(|a| a.gcd(&b) == 1)
+                .map(|a| Rational::new(a, b))
+                .collect();
+            let batched = z4c_possible_periods_batch(&cs, 2, contexts);
+            let single: Vec<(usize, PeriodSet)> = cs.iter().enumerate()
+                .filter_map(|(i, &c)| Some((i, z4c_possible_periods_search(c, 2, contexts)?)))
+                .collect();
+            assert_eq!(batched, single);
+        }
+        assert!(z4c_possible_periods_batch(&[], 2, contexts).is_empty());
+    }
+
+    #[test]
+    fn period_exponent_bounds() {
+        // Over Q: e <= 2 at 2, e <= 1 at 3, and only n = m, mr above that
+        assert_eq!(max_period_p_exponent(2, 1), 2);
+        assert_eq!(max_period_p_exponent(3, 1), 1);
+        assert_eq!(max_period_p_exponent(5, 1), 0);
+        assert_eq!(max_period_p_exponent(97, 1), 0);
+        // (1 - w) over 3 in Q(w): 3^(e-1) <= 2
+        assert_eq!(max_period_p_exponent(3, 2), 1);
+        // 5^(e-1) <= 6/4 and 2^(e-1) <= 8
+        assert_eq!(max_period_p_exponent(5, 3), 1);
+        assert_eq!(max_period_p_exponent(2, 4), 4);
+    }
+
+    #[test]
+    fn period_shapes_at_small_primes() {
+        // Everything reduces to a fixed point of z^2 mod 2 (0 and 1),
+        // with multiplier 0 and 2z = 0, so nothing more is possible
+        let pers = fast_possible_periods(Polynomial::new(vec![1, 0, 0], Some(2)));
+        assert_eq!(pers, [1].iter().copied().collect());
+        // z^2 + z + 1 mod 2 fixes 1 with multiplier 3 = 1, so
+        // m r 2^e for e <= 2 gives 1, 2 and 4
+        let pers = fast_possible_periods(Polynomial::new(vec![1, 1, 1], Some(2)));
+        assert_eq!(pers, [1, 2, 4].iter().copied().collect());
+        // z + 1 mod 2 has the 2-cycle 0 <-> 1 with multiplier 1
+        let pers = fast_possible_periods(Polynomial::new(vec![1, 1], Some(2)));
+        assert_eq!(pers, [2, 4, 8].iter().copied().collect());
+
+        // The rational 3-cycle of z^2 - 29/16 survives every good prime
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(-29, 16)]);
+        for p in 3..=100 {
+            if prime(p) && f.has_good_reduction(p) {
+                assert!(fast_possible_periods(f.do_reduction(p)).contains(3));
+            }
+        }
     }
 }
\ No newline at end of file
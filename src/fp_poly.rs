@@ -0,0 +1,511 @@
+// Dense polynomials over F_p, for when p is far too large to walk
+// every residue. Whether f has points mod p of (formal) period n is
+// whether the dynatomic polynomial Phi_n of f has a root in F_p, that
+// is whether gcd(Phi_n(x), x^p - x) isn't constant, and x^p can be
+// taken mod Phi_n by repeated squaring, so this costs about
+// deg(Phi_n)^2 log p instead of p. Factoring them (square-free,
+// distinct-degree, then equal-degree parts) gives the degrees of the
+// fields of definition of the periodic points
+
+use crate::math::*;
+use crate::util::Polynomial;
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FpPoly {
+    pub modulus: Modulus,
+    // Lowest degree first, without trailing zeros (so empty for 0)
+    coeffs: Vec<u64>,
+}
+
+#[allow(unused)]
+impl FpPoly {
+    // From coefficients lowest degree first, already reduced
+    pub fn new(coeffs: Vec<u64>, modulus: Modulus) -> FpPoly {
+        let mut res = FpPoly { modulus, coeffs };
+        res.trim();
+        res
+    }
+
+    // The same polynomial as f (which has to be mod a prime)
+    pub fn from_polynomial(f: &Polynomial) -> FpPoly {
+        let modulus = Modulus::new(f.p_mod.expect("polynomial not mod p"));
+        FpPoly::new(f.coeffs.iter().rev().map(|&c| modulus.reduce(c)).collect(), modulus)
+    }
+
+    pub fn zero(modulus: Modulus) -> FpPoly {
+        FpPoly { modulus, coeffs: Vec::new() }
+    }
+
+    pub fn constant(c: u64, modulus: Modulus) -> FpPoly {
+        FpPoly::new(vec![c], modulus)
+    }
+
+    pub fn x(modulus: Modulus) -> FpPoly {
+        FpPoly::new(vec![0, 1], modulus)
+    }
+
+    fn trim(&mut self) {
+        while self.coeffs.last() == Some(&0) {
+            self.coeffs.pop();
+        }
+    }
+
+    pub fn coeffs(&self) -> &[u64] {
+        &self.coeffs
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.coeffs.is_empty()
+    }
+
+    // The degree, taking that of 0 to be 0 as well
+    pub fn degree(&self) -> usize {
+        self.coeffs.len().saturating_sub(1)
+    }
+
+    pub fn leading(&self) -> u64 {
+        self.coeffs.last().copied().unwrap_or(0)
+    }
+
+    pub fn eval(&self, x: u64) -> u64 {
+        let m = self.modulus;
+        self.coeffs.iter().rev().fold(0, |res, &c| m.add(m.mul(res, x), c))
+    }
+
+    pub fn scale(&self, c: u64) -> FpPoly {
+        let m = self.modulus;
+        FpPoly::new(self.coeffs.iter().map(|&a| m.mul(a, c)).collect(), m)
+    }
+
+    // self divided by its leading coefficient
+    pub fn monic(&self) -> FpPoly {
+        match self.modulus.inverse(self.leading()) {
+            Some(inv) => self.scale(inv),
+            None => self.clone(),
+        }
+    }
+
+    pub fn derivative(&self) -> FpPoly {
+        let m = self.modulus;
+        FpPoly::new(
+            self.coeffs.iter().enumerate().skip(1)
+                .map(|(i, &c)| m.mul(m.reduce(i as i64), c))
+                .collect(),
+            m,
+        )
+    }
+
+    // (q, r) with self = q d + r and deg r < deg d (d nonzero)
+    pub fn div_rem(&self, d: &FpPoly) -> (FpPoly, FpPoly) {
+        assert!(!d.is_zero(), "division by the zero polynomial");
+        let m = self.modulus;
+        let n = d.coeffs.len();
+        if self.coeffs.len() < n {
+            return (FpPoly::zero(m), self.clone());
+        }
+        let inv = m.inverse(d.leading()).expect("leading coefficient not invertible");
+        let mut r = self.coeffs.clone();
+        let mut q = vec![0; r.len() - n + 1];
+        for i in (0..q.len()).rev() {
+            let c = m.mul(r[i + n - 1], inv);
+            q[i] = c;
+            if c != 0 {
+                for (a, &b) in r[i..i + n].iter_mut().zip(d.coeffs.iter()) {
+                    *a = m.sub(*a, m.mul(c, b));
+                }
+            }
+        }
+        r.truncate(n - 1);
+        (FpPoly::new(q, m), FpPoly::new(r, m))
+    }
+
+    pub fn rem(&self, d: &FpPoly) -> FpPoly {
+        self.div_rem(d).1
+    }
+
+    // The monic gcd (0 if both are 0)
+    pub fn gcd(&self, other: &FpPoly) -> FpPoly {
+        let mut a = self.clone();
+        let mut b = other.clone();
+        while !b.is_zero() {
+            let r = a.rem(&b);
+            a = b;
+            b = r;
+        }
+        a.monic()
+    }
+
+    // self^e mod d, by repeated squaring
+    pub fn pow_mod(&self, e: u64, d: &FpPoly) -> FpPoly {
+        let m = self.modulus;
+        let mut res = FpPoly::constant(1, m).rem(d);
+        let mut base = self.rem(d);
+        let mut e = e;
+        while e > 0 {
+            if e & 1 == 1 {
+                res = (&res * &base).rem(d);
+            }
+            base = (&base * &base).rem(d);
+            e >>= 1;
+        }
+        res
+    }
+
+    // self(g)
+    pub fn compose(&self, g: &FpPoly) -> FpPoly {
+        let m = self.modulus;
+        self.coeffs.iter().rev().fold(FpPoly::zero(m), |res, &c| &(&res * g) + &FpPoly::constant(c, m))
+    }
+
+    // The n-th dynatomic polynomial of self, the product of
+    // (self^d(x) - x)^mu(n/d) over the divisors d of n, whose roots
+    // are the points of formal period n
+    pub fn dynatomic(&self, n: usize) -> FpPoly {
+        let m = self.modulus;
+        let x = FpPoly::x(m);
+        let mut num = FpPoly::constant(1, m);
+        let mut den = FpPoly::constant(1, m);
+        let mut iterate = x.clone();
+        for d in 1..=n {
+            iterate = self.compose(&iterate);
+            if !n.is_multiple_of(d) {
+                continue;
+            }
+            match mobius(n / d) {
+                1 => num = &num * &(&iterate - &x),
+                -1 => den = &den * &(&iterate - &x),
+                _ => {}
+            }
+        }
+        let (q, r) = num.div_rem(&den);
+        debug_assert!(r.is_zero());
+        q
+    }
+
+    // The product of the x - a for the roots a of self in F_p
+    pub fn roots_part(&self) -> FpPoly {
+        let m = self.modulus;
+        let x = FpPoly::x(m);
+        let xp = x.pow_mod(m.value() as u64, self);
+        self.gcd(&(&xp - &x))
+    }
+
+    // The resultant (0 if either is 0), by the Euclidean algorithm
+    pub fn resultant(&self, other: &FpPoly) -> u64 {
+        let m = self.modulus;
+        if self.is_zero() || other.is_zero() {
+            return 0;
+        }
+        let mut a = self.clone();
+        let mut b = other.clone();
+        let mut res = m.reduce(1);
+        // res(a, b) = (-1)^(deg a deg b) lc(b)^(deg a - deg r) res(b, r)
+        // for r = a mod b
+        while b.degree() > 0 {
+            let r = a.rem(&b);
+            if r.is_zero() {
+                return 0;
+            }
+            if a.degree() * b.degree() % 2 == 1 {
+                res = m.sub(0, res);
+            }
+            res = m.mul(res, m.pow(b.leading(), (a.degree() - r.degree()) as u64));
+            a = b;
+            b = r;
+        }
+        m.mul(res, m.pow(b.leading(), a.degree() as u64))
+    }
+
+    // (-1)^(d(d-1)/2) res(f, f') / lc(f), taking f' to have degree
+    // d - 1 (so for p not dividing d)
+    pub fn discriminant(&self) -> u64 {
+        let m = self.modulus;
+        let d = self.degree();
+        let inv = m.inverse(self.leading()).expect("discriminant of 0");
+        let res = m.mul(self.resultant(&self.derivative()), inv);
+        if (d * d.saturating_sub(1) / 2) % 2 == 1 { m.sub(0, res) } else { res }
+    }
+
+    // The monic square-free part of self^(1/p), when self' = 0
+    fn pth_root(&self) -> FpPoly {
+        let p = self.modulus.value() as usize;
+        FpPoly::new(self.coeffs.iter().step_by(p).copied().collect(), self.modulus)
+    }
+
+    // Square-free decomposition (Yun's algorithm, with p-th roots for
+    // the factors whose multiplicity is a multiple of p): monic
+    // square-free (g, k) with self = lc * prod g^k, in increasing k
+    pub fn square_free_decomposition(&self) -> Vec<(FpPoly, usize)> {
+        let f = self.monic();
+        if f.degree() == 0 {
+            return Vec::new();
+        }
+        let p = self.modulus.value() as usize;
+        let mut res = Vec::new();
+        let mut w = f.gcd(&f.derivative());
+        let mut v = f.div_rem(&w).0;
+        let mut k = 1;
+        // v is the product of the factors of multiplicity >= k not
+        // divisible by p, w what's left of f
+        while v.degree() > 0 {
+            let g = v.gcd(&w);
+            let factor = v.div_rem(&g).0;
+            if factor.degree() > 0 {
+                res.push((factor, k));
+            }
+            w = w.div_rem(&g).0;
+            v = g;
+            k += 1;
+        }
+        // Now w = h^p for some h
+        if w.degree() > 0 {
+            for (g, j) in w.pth_root().square_free_decomposition() {
+                res.push((g, j * p));
+            }
+        }
+        res.sort_by_key(|(_, k)| *k);
+        res
+    }
+
+    // For monic square-free self, the products of its irreducible
+    // factors of each degree d that has some, as (product, d)
+    pub fn distinct_degree_factorization(&self) -> Vec<(FpPoly, usize)> {
+        let m = self.modulus;
+        let p = m.value() as u64;
+        let x = FpPoly::x(m);
+        let mut res = Vec::new();
+        let mut f = self.monic();
+        // x^(p^d) mod f
+        let mut xq = x.clone();
+        let mut d = 0;
+        while f.degree() >= 2 * (d + 1) {
+            d += 1;
+            xq = xq.pow_mod(p, &f);
+            let g = f.gcd(&(&xq - &x));
+            if g.degree() > 0 {
+                f = f.div_rem(&g).0;
+                xq = xq.rem(&f);
+                res.push((g, d));
+            }
+        }
+        if f.degree() > 0 {
+            let d = f.degree();
+            res.push((f, d));
+        }
+        res
+    }
+
+    // For monic self a product of distinct irreducibles of degree d,
+    // those irreducibles (Cantor-Zassenhaus, with a fixed seed so the
+    // order of the work, if not the result, is reproducible)
+    pub fn equal_degree_factorization(&self, d: usize) -> Vec<FpPoly> {
+        let f = self.monic();
+        if f.degree() <= d {
+            return vec![f];
+        }
+        let m = self.modulus;
+        let p = m.value() as u64;
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64 ^ f.degree() as u64;
+        loop {
+            let a = FpPoly::new(
+                (0..f.degree()).map(|_| splitmix(&mut seed) % p).collect(),
+                m,
+            );
+            if a.degree() == 0 {
+                continue;
+            }
+            let b = if p == 2 {
+                // The trace a + a^2 + ... + a^(2^(kd - 1)) of a
+                let mut t = a.rem(&f);
+                let mut sum = t.clone();
+                for _ in 1..d {
+                    t = (&t * &t).rem(&f);
+                    sum = &sum + &t;
+                }
+                sum
+            } else {
+                // a^((p^d - 1)/2), as (a^(1 + p + ... + p^(d-1)))^((p - 1)/2)
+                let mut frob = a.rem(&f);
+                let mut norm = frob.clone();
+                for _ in 1..d {
+                    frob = frob.pow_mod(p, &f);
+                    norm = (&norm * &frob).rem(&f);
+                }
+                &norm.pow_mod((p - 1) / 2, &f) - &FpPoly::constant(1, m)
+            };
+            let g = f.gcd(&b);
+            if g.degree() > 0 && g.degree() < f.degree() {
+                let mut res = g.equal_degree_factorization(d);
+                res.extend(f.div_rem(&g).0.equal_degree_factorization(d));
+                return res;
+            }
+        }
+    }
+
+    // The monic irreducible factors of self with their multiplicities,
+    // by degree and then coefficients
+    pub fn factor(&self) -> Vec<(FpPoly, usize)> {
+        let mut res = Vec::new();
+        for (g, k) in self.square_free_decomposition() {
+            for (h, d) in g.distinct_degree_factorization() {
+                res.extend(h.equal_degree_factorization(d).into_iter().map(|q| (q, k)));
+            }
+        }
+        res.sort_by(|(a, _), (b, _)| a.degree().cmp(&b.degree()).then_with(|| a.coeffs.cmp(&b.coeffs)));
+        res
+    }
+
+    // The (degree, multiplicity) of each irreducible factor, sorted
+    pub fn factor_degrees(&self) -> Vec<(usize, usize)> {
+        let mut res: Vec<(usize, usize)> = self.factor().iter().map(|(g, k)| (g.degree(), *k)).collect();
+        res.sort_unstable();
+        res
+    }
+
+    // Does self have a point of formal period n in F_p? For a cycle of
+    // length m with multiplier of order r, these are n = m, m r and
+    // m r p^e, as in fast_possible_periods (for p > n, only the first two)
+    pub fn has_formal_period(&self, n: usize) -> bool {
+        let phi = self.dynatomic(n);
+        phi.degree() > 0 && phi.roots_part().degree() > 0
+    }
+}
+
+// The next of a sequence of pseudo-random u64s (splitmix64)
+fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl<'a> Add<&'a FpPoly> for &'a FpPoly {
+    type Output = FpPoly;
+
+    fn add(self, other: &FpPoly) -> FpPoly {
+        let m = self.modulus;
+        let n = self.coeffs.len().max(other.coeffs.len());
+        FpPoly::new((0..n).map(|i| {
+            m.add(self.coeffs.get(i).copied().unwrap_or(0), other.coeffs.get(i).copied().unwrap_or(0))
+        }).collect(), m)
+    }
+}
+
+impl<'a> Sub<&'a FpPoly> for &'a FpPoly {
+    type Output = FpPoly;
+
+    fn sub(self, other: &FpPoly) -> FpPoly {
+        let m = self.modulus;
+        let n = self.coeffs.len().max(other.coeffs.len());
+        FpPoly::new((0..n).map(|i| {
+            m.sub(self.coeffs.get(i).copied().unwrap_or(0), other.coeffs.get(i).copied().unwrap_or(0))
+        }).collect(), m)
+    }
+}
+
+impl<'a> Mul<&'a FpPoly> for &'a FpPoly {
+    type Output = FpPoly;
+
+    // Schoolbook, but when p < 2^32 the products are summed exactly
+    // in a u128 and only reduced once per coefficient
+    fn mul(self, other: &FpPoly) -> FpPoly {
+        let m = self.modulus;
+        if self.is_zero() || other.is_zero() {
+            return FpPoly::zero(m);
+        }
+        let n = self.coeffs.len() + other.coeffs.len() - 1;
+        let p = m.value() as u64;
+        let coeffs = if p < 1 << 32 {
+            let mut acc = vec![0u128; n];
+            for (i, &a) in self.coeffs.iter().enumerate() {
+                for (s, &b) in acc[i..].iter_mut().zip(other.coeffs.iter()) {
+                    *s += (a * b) as u128;
+                }
+            }
+            acc.into_iter().map(|s| (s % p as u128) as u64).collect()
+        } else {
+            let mut acc = vec![0u64; n];
+            for (i, &a) in self.coeffs.iter().enumerate() {
+                for (s, &b) in acc[i..].iter_mut().zip(other.coeffs.iter()) {
+                    *s = m.add(*s, m.mul(a, b));
+                }
+            }
+            acc
+        };
+        FpPoly::new(coeffs, m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ds_helper::fast_possible_periods;
+
+    #[test]
+    fn fp_poly_arithmetic() {
+        let m = Modulus::new(7);
+        let f = FpPoly::new(vec![1, 2, 3], m);
+        let g = FpPoly::new(vec![6, 1], m);
+        let (q, r) = (&f * &g).div_rem(&g);
+        assert_eq!((q, r.is_zero()), (f.clone(), true));
+        // (x - 1)(x - 2) and (x - 1)(x - 3) have gcd x - 1
+        let a = &FpPoly::new(vec![6, 1], m) * &FpPoly::new(vec![5, 1], m);
+        let b = &FpPoly::new(vec![6, 1], m) * &FpPoly::new(vec![4, 1], m);
+        assert_eq!(a.gcd(&b), FpPoly::new(vec![6, 1], m));
+        // x^2 + 1 has no roots mod 7, x^2 - 2 has two
+        assert_eq!(FpPoly::new(vec![1, 0, 1], m).roots_part().degree(), 0);
+        assert_eq!(FpPoly::new(vec![5, 0, 1], m).roots_part().degree(), 2);
+        assert_eq!(f.derivative(), FpPoly::new(vec![2, 6], m));
+
+        // Phi_2 of z^2 + c is z^2 + z + c + 1
+        let f = FpPoly::new(vec![3, 0, 1], m);
+        assert_eq!(f.dynatomic(2), FpPoly::new(vec![4, 1, 1], m));
+    }
+
+    #[test]
+    fn factorization() {
+        for &p in &[2i64, 3, 5, 101, 1_000_000_007] {
+            let m = Modulus::new(p);
+            let lin = |a: i64| FpPoly::new(vec![m.reduce(-a), 1], m);
+            // x^2 + x + 1 is irreducible mod 2 and 5, not mod 3 or 101
+            let quad = FpPoly::new(vec![1, 1, 1], m);
+            let f = &(&(&lin(1) * &lin(1)) * &(&lin(2) * &quad)) * &(&quad * &quad);
+            let factors = f.factor();
+            let product = factors.iter().fold(FpPoly::constant(1, m), |acc, (g, k)| {
+                (0..*k).fold(acc, |acc, _| &acc * g)
+            });
+            assert_eq!(product, f.monic(), "p = {}", p);
+            if p == 2 || p == 5 {
+                assert_eq!(f.factor_degrees(), vec![(1, 1), (1, 2), (2, 3)]);
+            }
+        }
+        let m = Modulus::new(5);
+        let quad = FpPoly::new(vec![1, 1, 1], m);
+        let f = &(&FpPoly::new(vec![4, 1], m) * &FpPoly::new(vec![4, 1], m)) * &quad;
+        assert_eq!(f.factor_degrees(), vec![(1, 2), (2, 1)]);
+        // x^5 - x = x(x - 1)...(x - 4) mod 5, and x^10 = (x^2)^5
+        let f = FpPoly::new(vec![0, 4, 0, 0, 0, 1], m);
+        assert_eq!(f.factor_degrees(), vec![(1, 1); 5]);
+        let mut coeffs = vec![0; 11];
+        coeffs[10] = 1;
+        assert_eq!(FpPoly::new(coeffs, m).factor_degrees(), vec![(1, 10)]);
+        // x^4 + 1 mod 3 splits into two quadratics
+        assert_eq!(Polynomial::new(vec![1, 0, 0, 0, 1], Some(3)).factor_degrees(), vec![(2, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn gcd_periods_match_enumeration() {
+        for &p in &[101i64, 211, 1009] {
+            for c in 0..20 {
+                let f = Polynomial::new(vec![1, 0, 0, 0, c], Some(p));
+                let g = FpPoly::from_polynomial(&f);
+                let periods = fast_possible_periods(f);
+                for n in 1..=4 {
+                    assert_eq!(g.has_formal_period(n), periods.contains(n), "p = {}, c = {}, n = {}", p, c, n);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Cycle types of the dynatomic polynomial Phi_n of a polynomial over Q
+// across primes. At a prime where Phi_n stays square-free, the degrees
+// of its irreducible factors mod p are the cycle type of Frobenius in
+// the Galois group of Phi_n, so by Chebotarev their frequencies over
+// many primes estimate the proportions of each cycle type in it (and
+// a missing type, say an n-cycle, is evidence of a small group)
+
+use crate::bifurcation::dynatomic_degree;
+use crate::fp_poly::FpPoly;
+use crate::math::*;
+use crate::util::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub struct CycleTypeStats {
+    pub n: usize,
+    pub prime_max: usize,
+    // Degree of Phi_n over Q
+    pub degree: usize,
+    // Primes p <= prime_max of good reduction where Phi_n mod p is
+    // square-free, and how often each list of factor degrees appears
+    pub primes: usize,
+    pub patterns: BTreeMap<Vec<usize>, usize>,
+    // Good primes where Phi_n mod p has a repeated factor
+    pub ramified: usize,
+    // Primes (of the square-free ones) with an F_p-rational n-cycle
+    pub with_cycle: usize,
+}
+
+// Reduce Phi_n of f mod each prime up to prime_max (or a conjugate of
+// f with good reduction, as in possible_periods_search) and collect
+// the factorization patterns
+pub fn cycle_type_statistics(f: &PolynomialInQ, n: usize, prime_max: usize) -> CycleTypeStats {
+    let mut stats = CycleTypeStats {
+        n,
+        prime_max,
+        degree: dynatomic_degree(f.degree(), n),
+        primes: 0,
+        patterns: BTreeMap::new(),
+        ramified: 0,
+        with_cycle: 0,
+    };
+    for p in (2..=prime_max).filter(|&p| small_prime(p)) {
+        let red = if f.has_good_reduction(p) {
+            f.do_reduction(p)
+        } else if let Some((g, _, _)) = f.good_reduction_model(p) {
+            g.do_reduction(p)
+        } else {
+            continue;
+        };
+        let red = FpPoly::from_polynomial(&red);
+        let factors = red.dynatomic(n).factor();
+        if factors.iter().any(|&(_, k)| k > 1) {
+            stats.ramified += 1;
+            continue;
+        }
+        stats.primes += 1;
+        *stats.patterns.entry(factors.iter().map(|(g, _)| g.degree()).collect()).or_insert(0) += 1;
+        // Roots of Phi_n might have a smaller exact period
+        let has_cycle = factors.iter()
+            .filter(|(g, _)| g.degree() == 1)
+            .any(|(g, _)| exact_period(&red, red.modulus.sub(0, g.coeffs()[0]), n) == Some(n));
+        if has_cycle {
+            stats.with_cycle += 1;
+        }
+    }
+    stats
+}
+
+// The exact period of x under f, if it's periodic with period <= max
+fn exact_period(f: &FpPoly, x: u64, max: usize) -> Option<usize> {
+    let mut y = x;
+    for k in 1..=max {
+        y = f.eval(y);
+        if y == x {
+            return Some(k);
+        }
+    }
+    None
+}
+
+impl fmt::Display for CycleTypeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Phi_{} has degree {}; factorization patterns over {} primes p <= {} ({} more with a repeated factor):",
+                 self.n, self.degree, self.primes, self.prime_max, self.ramified)?;
+        let mut patterns: Vec<(&Vec<usize>, &usize)> = self.patterns.iter().collect();
+        patterns.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        if self.primes == 0 {
+            writeln!(f, "  (none)")?;
+        }
+        for (pattern, &count) in patterns {
+            writeln!(f, "  {:?}: {} ({:.1}%)", pattern, count, 100.0 * count as f64 / self.primes as f64)?;
+        }
+        write!(f, "Primes with an F_p-rational {}-cycle: {} of {}", self.n, self.with_cycle, self.primes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_type_frequencies() {
+        // z^2 - 29/16 has a rational 3-cycle, which survives mod every
+        // prime where Phi_3 stays square-free
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(-29, 16)]);
+        let stats = cycle_type_statistics(&f, 3, 500);
+        assert_eq!(stats.degree, 6);
+        assert_eq!(stats.with_cycle, stats.primes);
+        assert!(stats.patterns.keys().all(|pattern| pattern.iter().filter(|&&d| d == 1).count() >= 3));
+
+        // Phi_2 of z^2 + 1 is x^2 + x + 2, which splits exactly when
+        // -7 is a square mod p, so about half the time
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::one()]);
+        let stats = cycle_type_statistics(&f, 2, 2000);
+        let split = stats.patterns[&vec![1, 1]];
+        assert_eq!(split + stats.patterns[&vec![2]], stats.primes);
+        assert!(split * 5 > stats.primes * 2 && split * 5 < stats.primes * 3);
+        assert_eq!(stats.with_cycle, split);
+        assert_eq!(stats.ramified, 1);
+        // 2 counts too: x^2 + x + 2 = x (x + 1) mod 2
+        let stats = cycle_type_statistics(&f, 2, 2);
+        assert_eq!((stats.primes, stats.with_cycle), (1, 1));
+        let stats = cycle_type_statistics(&f, 2, 1);
+        assert!(!format!("{}", stats).contains("NaN"));
+    }
+}
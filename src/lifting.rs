@@ -0,0 +1,421 @@
+// Finding the actual cycles behind a period that survives the
+// search: lift a cycle of the reduction mod p to Z_p with Hensel's
+// lemma, reconstruct a point over Q or Q(w) from its p-adic
+// approximation, and check the cycle exactly
+
+use crate::util::*;
+use crate::math::*;
+use crate::ds_helper::periodic_cycles;
+use num_integer::Integer;
+use std::convert::TryFrom;
+
+// Work mod the largest power of p at most this, so
+// that products of residues still fit in an i128
+const MAX_PRECISION: i128 = 1 << 62;
+
+// Primes tried by rational_cycles and qw_cycles
+const LIFTING_PRIMES: usize = 100;
+
+// A cycle can fail to lift at a given prime (its multiplier
+// might be 1 mod p, or it might have a shorter period mod p),
+// so try several and keep every distinct cycle found
+pub fn rational_cycles(f: &PolynomialInQ, n: usize) -> Vec<Vec<Rational>> {
+    let mut res: Vec<Vec<Rational>> = Vec::new();
+    for p in 2..=LIFTING_PRIMES {
+        if !prime(p) {
+            continue;
+        }
+        for cycle in lift_cycles_q(f, p, n) {
+            if !res.iter().any(|c| c.contains(&cycle[0])) {
+                res.push(cycle);
+            }
+        }
+    }
+    res
+}
+
+pub fn qw_cycles(f: &PolynomialInQw, n: usize) -> Vec<Vec<QwElement>> {
+    let mut res: Vec<Vec<QwElement>> = Vec::new();
+    for p in 2..=LIFTING_PRIMES {
+        if !prime(p) {
+            continue;
+        }
+        for cycle in lift_cycles_qw(f, p as i64, n) {
+            if !res.iter().any(|c| c.contains(&cycle[0])) {
+                res.push(cycle);
+            }
+        }
+    }
+    res
+}
+
+// The cycles of f over Q of exact period n which reduce to an
+// n-cycle mod p with multiplier != 1 (all of them, as long as
+// every such cycle has height below about 2^30)
+pub fn lift_cycles_q(f: &PolynomialInQ, p: usize, n: usize) -> Vec<Vec<Rational>> {
+    if !f.has_good_reduction(p) {
+        return Vec::new();
+    }
+    let m = precision(p as i64);
+    let coeffs: Vec<i128> = f.coeffs.iter()
+        .map(|c| reduce_mod(c.numer as i128, c.denom as i128, m))
+        .collect();
+    let exact: Vec<Exact> = f.coeffs.iter()
+        .map(|c| Exact::new(c.numer as i128, 0, c.denom as i128).unwrap())
+        .collect();
+
+    periodic_cycles(&f.do_reduction(p)).into_iter()
+        .filter(|cycle| cycle.len() == n)
+        .filter_map(|cycle| {
+            let x = hensel_lift(&coeffs, m, cycle[0] as i128, n)?;
+            let (a, d) = rational_reconstruction(x, m)?;
+            exact_cycle(&exact, Exact::new(a, 0, d)?, n)?
+                .into_iter()
+                .map(|x| x.to_rational())
+                .collect()
+        }).collect()
+}
+
+// The same over Q(w), at a prime p = 1 mod 3 (along both primes
+// of Z[w] above it). Points are found as (a + bw) / d with a, b, d
+// of size around p^(N/3), N the precision
+pub fn lift_cycles_qw(f: &PolynomialInQw, p: i64, n: usize) -> Vec<Vec<QwElement>> {
+    // At 3 the root of x^2 + x + 1 doesn't lift (it's a double root)
+    if p == 3 || !has_qw_homomorphism(p) {
+        return Vec::new();
+    }
+    let m = precision(p);
+    let exact: Option<Vec<Exact>> = f.coeffs.iter().map(|&c| Exact::from_qw(c)).collect();
+    let exact = match exact {
+        Some(exact) => exact,
+        None => return Vec::new(),
+    };
+
+    let mut res = Vec::new();
+    for (w, red) in qw_homomorphisms(p).into_iter().zip(f.reductions(p)) {
+        let red = match red {
+            Some(red) => red,
+            None => continue,
+        };
+        let w = match lift_root(&[1, 1, 1], m, w as i128) {
+            Some(w) => w,
+            None => continue,
+        };
+        // Reduce numerators and denominators separately: a denominator
+        // can be a unit along w while its norm isn't
+        let image = |x: EisensteinInteger| {
+            let (a, b) = x.coords();
+            (a as i128 + b as i128 * w).rem_euclid(m)
+        };
+        let coeffs: Option<Vec<i128>> = f.coeffs.iter()
+            .map(|c| Some(image(c.numer()) * inverse_mod(image(c.denom()), m)? % m))
+            .collect();
+        let coeffs = match coeffs {
+            Some(coeffs) => coeffs,
+            None => continue,
+        };
+        for cycle in periodic_cycles(&red) {
+            if cycle.len() != n {
+                continue;
+            }
+            let x = match hensel_lift(&coeffs, m, cycle[0] as i128, n) {
+                Some(x) => x,
+                None => continue,
+            };
+            // Any vector of the reduced basis could be the point
+            for (a, b, d) in qw_reconstruction(x, w, m) {
+                let found = Exact::new(a, b, d)
+                    .and_then(|x| exact_cycle(&exact, x, n))
+                    .and_then(|c| c.into_iter().map(|x| x.to_qw()).collect::<Option<Vec<_>>>());
+                if let Some(found) = found {
+                    res.push(found);
+                    break;
+                }
+            }
+        }
+    }
+    res
+}
+
+fn precision(p: i64) -> i128 {
+    let p = p as i128;
+    let mut m = p;
+    while m * p <= MAX_PRECISION {
+        m *= p;
+    }
+    m
+}
+
+fn inverse_mod(a: i128, m: i128) -> Option<i128> {
+    let a = a.rem_euclid(m);
+    if a.gcd(&m) != 1 {
+        return None;
+    }
+    Some(mod_inverse(a, m))
+}
+
+// a/d mod m, for d a unit mod m
+fn reduce_mod(a: i128, d: i128, m: i128) -> i128 {
+    a.rem_euclid(m) * inverse_mod(d, m).unwrap() % m
+}
+
+// The value and derivative at x mod m of the
+// polynomial with the given coefficients (leading first)
+fn eval_mod(coeffs: &[i128], x: i128, m: i128) -> (i128, i128) {
+    let mut val = 0;
+    let mut der = 0;
+    for &c in coeffs {
+        der = (der * x + val) % m;
+        val = (val * x + c) % m;
+    }
+    (val.rem_euclid(m), der.rem_euclid(m))
+}
+
+// Newton's method for a simple root mod p of the given polynomial
+fn lift_root(coeffs: &[i128], m: i128, x: i128) -> Option<i128> {
+    let mut x = x;
+    for _ in 0..128 {
+        let (val, der) = eval_mod(coeffs, x, m);
+        if val == 0 {
+            return Some(x);
+        }
+        x = (x - val * inverse_mod(der, m)? % m).rem_euclid(m);
+    }
+    None
+}
+
+// Newton's method for f^n(x) = x, starting from a point x of
+// period n mod p. The derivative of f^n(x) - x is the multiplier
+// minus 1, so this converges when the multiplier isn't 1 mod p
+fn hensel_lift(coeffs: &[i128], m: i128, x: i128, n: usize) -> Option<i128> {
+    let mut x = x;
+    for _ in 0..128 {
+        let mut y = x;
+        let mut multiplier = 1;
+        for _ in 0..n {
+            let (val, der) = eval_mod(coeffs, y, m);
+            multiplier = multiplier * der % m;
+            y = val;
+        }
+        let err = (y - x).rem_euclid(m);
+        if err == 0 {
+            return Some(x);
+        }
+        x = (x - err * inverse_mod(multiplier - 1, m)? % m).rem_euclid(m);
+    }
+    None
+}
+
+// The a/d = x mod m with |a|, d <= sqrt(m/2), if there is one
+// (it's unique), from the extended Euclidean algorithm
+fn rational_reconstruction(x: i128, m: i128) -> Option<(i128, i128)> {
+    let bound = (m / 2).isqrt();
+    let (mut r0, mut r1) = (m, x.rem_euclid(m));
+    let (mut t0, mut t1) = (0i128, 1i128);
+    while r1 > bound {
+        let q = r0 / r1;
+        (r0, r1) = (r1, r0 - q * r1);
+        (t0, t1) = (t1, t0 - q * t1);
+    }
+    if t1 == 0 || t1.abs() > bound || r1.gcd(&t1) != 1 {
+        return None;
+    }
+    Some((r1 * t1.signum(), t1.abs()))
+}
+
+// Short vectors (a, b, d) with a + bw = dx mod m (w standing for
+// the given root of x^2 + x + 1 mod m), from an LLL reduced
+// basis of the lattice of all of them
+fn qw_reconstruction(x: i128, w: i128, m: i128) -> Vec<(i128, i128, i128)> {
+    let mut basis = [[m, 0, 0], [(m - w) % m, 1, 0], [x, 0, 1]];
+    lll_reduce(&mut basis);
+    basis.iter()
+        .filter(|v| v[2] != 0)
+        .map(|v| (v[0], v[1], v[2]))
+        .collect()
+}
+
+fn gram_schmidt(basis: &[[i128; 3]; 3]) -> ([[f64; 3]; 3], [[f64; 3]; 3]) {
+    let dot = |u: &[f64; 3], v: &[f64; 3]| u[0] * v[0] + u[1] * v[1] + u[2] * v[2];
+    let mut ortho = [[0.0; 3]; 3];
+    let mut mu = [[0.0; 3]; 3];
+    for i in 0..3 {
+        let b = [basis[i][0] as f64, basis[i][1] as f64, basis[i][2] as f64];
+        ortho[i] = b;
+        for j in 0..i {
+            let oj = ortho[j];
+            mu[i][j] = dot(&b, &oj) / dot(&oj, &oj);
+            for (x, y) in ortho[i].iter_mut().zip(oj.iter()) {
+                *x -= mu[i][j] * y;
+            }
+        }
+    }
+    (ortho, mu)
+}
+
+// The textbook LLL algorithm (delta = 3/4), with floating point
+// Gram-Schmidt; this only needs to be good enough to find a
+// point which is then checked exactly
+fn lll_reduce(basis: &mut [[i128; 3]; 3]) {
+    let norm = |u: &[f64; 3]| u[0] * u[0] + u[1] * u[1] + u[2] * u[2];
+    let mut k = 1;
+    for _ in 0..10_000 {
+        if k >= 3 {
+            return;
+        }
+        for j in (0..k).rev() {
+            let q = gram_schmidt(basis).1[k][j].round() as i128;
+            let bj = basis[j];
+            for (x, y) in basis[k].iter_mut().zip(bj.iter()) {
+                *x -= q * y;
+            }
+        }
+        let (ortho, mu) = gram_schmidt(basis);
+        if norm(&ortho[k]) >= (0.75 - mu[k][k-1] * mu[k][k-1]) * norm(&ortho[k-1]) {
+            k += 1;
+        } else {
+            basis.swap(k, k-1);
+            k = 1.max(k - 1);
+        }
+    }
+}
+
+// (a + bw) / d in Q(w), with d > 0 and gcd(a, b, d) = 1 (so
+// equality is equality of fields), and arithmetic that fails
+// instead of overflowing. Q is the part with b = 0
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Exact {
+    a: i128,
+    b: i128,
+    d: i128,
+}
+
+impl Exact {
+    fn new(a: i128, b: i128, d: i128) -> Option<Exact> {
+        if d == 0 {
+            return None;
+        }
+        let g = a.gcd(&b).gcd(&d) * d.signum();
+        Some(Exact { a: a / g, b: b / g, d: d / g })
+    }
+
+    fn from_qw(c: QwElement) -> Option<Exact> {
+        // Clear the denominator with its conjugate
+        let denom = c.denom();
+        let (a, b) = c.numer().product(denom.conjugate()).coords();
+        Exact::new(a as i128, b as i128, denom.norm_sq() as i128)
+    }
+
+    fn to_rational(self) -> Option<Rational> {
+        if self.b != 0 {
+            return None;
+        }
+        Some(Rational::new(i64::try_from(self.a).ok()?, i64::try_from(self.d).ok()?))
+    }
+
+    fn to_qw(self) -> Option<QwElement> {
+        let a = i64::try_from(self.a).ok()?;
+        let b = i64::try_from(self.b).ok()?;
+        let d = i64::try_from(self.d).ok()?;
+        Some(QwElement::new(EisensteinInteger::new(a, b), EisensteinInteger::new(d, 0)))
+    }
+
+    fn checked_add(self, other: Exact) -> Option<Exact> {
+        Exact::new(
+            self.a.checked_mul(other.d)?.checked_add(other.a.checked_mul(self.d)?)?,
+            self.b.checked_mul(other.d)?.checked_add(other.b.checked_mul(self.d)?)?,
+            self.d.checked_mul(other.d)?,
+        )
+    }
+
+    // (a + bw)(a' + b'w) = aa' - bb' + (ab' + a'b - bb')w
+    fn checked_mul(self, other: Exact) -> Option<Exact> {
+        let bb = self.b.checked_mul(other.b)?;
+        Exact::new(
+            self.a.checked_mul(other.a)?.checked_sub(bb)?,
+            self.a.checked_mul(other.b)?.checked_add(other.a.checked_mul(self.b)?)?.checked_sub(bb)?,
+            self.d.checked_mul(other.d)?,
+        )
+    }
+}
+
+fn eval_exact(coeffs: &[Exact], x: Exact) -> Option<Exact> {
+    let mut res = Exact::new(0, 0, 1)?;
+    for &c in coeffs {
+        res = res.checked_mul(x)?.checked_add(c)?;
+    }
+    Some(res)
+}
+
+// The orbit of x, if x has exact period n
+fn exact_cycle(coeffs: &[Exact], x: Exact, n: usize) -> Option<Vec<Exact>> {
+    let mut orbit = vec![x];
+    let mut y = eval_exact(coeffs, x)?;
+    while y != x {
+        if orbit.len() == n {
+            return None;
+        }
+        orbit.push(y);
+        y = eval_exact(coeffs, y)?;
+    }
+    if orbit.len() == n {
+        Some(orbit)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn as_sorted(mut cycle: Vec<Rational>) -> Vec<Rational> {
+        cycle.sort();
+        cycle
+    }
+
+    #[test]
+    fn lift_rational_cycles() {
+        // z^2 - 29/16 has the 3-cycle -1/4 -> -7/4 -> 5/4, and no rational fixed points
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(-29, 16)]);
+        let cycles = rational_cycles(&f, 3);
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(as_sorted(cycles[0].clone()),
+                   vec![Rational::new(-7, 4), Rational::new(-1, 4), Rational::new(5, 4)]);
+        assert!(rational_cycles(&f, 1).is_empty());
+
+        // z^4 - 1 has the 2-cycle 0 <-> -1
+        let f = PolynomialInQ::from(vec![
+            Rational::one(), Rational::zero(), Rational::zero(), Rational::zero(), Rational::from_integer(-1)
+        ]);
+        let cycles = rational_cycles(&f, 2);
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(as_sorted(cycles[0].clone()), vec![Rational::from_integer(-1), Rational::zero()]);
+
+        let m = precision(3);
+        assert_eq!(rational_reconstruction(reduce_mod(-7, 4, m), m), Some((-7, 4)));
+    }
+
+    #[test]
+    fn lift_qw_cycles() {
+        let w = QwElement::from_integer(EisensteinInteger::new(0, 1));
+        let one = QwElement::one();
+        let zero = QwElement::zero();
+
+        // z^2 has the 2-cycle w <-> w^2, which isn't over Q
+        let f = PolynomialInQw::from(vec![one, zero, zero]);
+        let cycles = qw_cycles(&f, 2);
+        assert_eq!(cycles.len(), 1);
+        assert!(cycles[0].contains(&w) && cycles[0].contains(&(w * w)));
+
+        // (3 + w) z^2 has the 2-cycle w/(3 + w) <-> w^2/(3 + w)
+        let a = QwElement::from_integer(EisensteinInteger::new(3, 1));
+        let f = PolynomialInQw::from(vec![a, zero, zero]);
+        let cycles = qw_cycles(&f, 2);
+        assert_eq!(cycles.len(), 1);
+        assert!(cycles[0].contains(&(w / a)));
+        for x in &cycles[0] {
+            assert_eq!(f.eval(f.eval(*x)), *x);
+        }
+    }
+}
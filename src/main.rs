@@ -1,17 +1,39 @@
-#![feature(euclidean_division, test)]
+#![feature(test)]
 #![allow(non_snake_case)]
 mod math;
 mod util;
 mod ds_helper;
+mod real_dynamics;
+mod period_set;
+mod period_table;
+mod fp_poly;
+mod bifurcation;
+mod galois;
+mod cycle_structure;
+mod point_bounds;
+mod configuration;
+mod prime_context;
+mod schedule;
+mod lifting;
+mod sieve;
 
-use math::*;
 use util::*;
 use ds_helper::*;
-use rayon::prelude::*;
+use real_dynamics::*;
+use lifting::*;
+use sieve::*;
+use prime_context::*;
+use schedule::*;
+use bifurcation::*;
+use galois::*;
+use point_bounds::*;
+use configuration::*;
+use cycle_structure::{CycleStructure, MAX_STRUCTURE_PRIME};
 
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::OnceLock;
 
-use num_integer::{Integer};
 use std::f32::consts::PI;
 
 extern crate num_complex;
@@ -29,44 +51,162 @@ pub fn format_search_result<T: fmt::Display, V: fmt::Debug>(ch: T, set: V) -> St
     format!("Check {}, since I can't rule out periods in: {:?}", ch, set)
 }
 
+pub fn format_found_cycle<T: fmt::Display, V: fmt::Display>(ch: T, cycle: &[V]) -> String {
+    let points: Vec<String> = cycle.iter().map(|x| x.to_string()).collect();
+    format!("Found a {}-cycle for {}: {}", cycle.len(), ch, points.join(" -> "))
+}
+
+// Periods whose bifurcation parameters z4c candidates are checked
+// against (D_4 of z^4 + c would already have degree over 14000)
+const Z4_BIFURCATION_PERIODS: usize = 3;
+
 // Search through a given parameter space
-// (uses z^4 + c, with appropriate optimizations)
-pub fn search_z4_opt(height_max: i64, height_min: i64) {
+// (uses z^4 + c, with appropriate optimizations). With prime_power
+// k > 1, the candidates are also refined modulo p^k, and a count of
+// how many more that rules out is printed at the end. Candidates on
+// the bifurcation locus of a small period are pointed out. With
+// bounds, each candidate also gets upper bounds on its rational
+// cycles of the periods that survive (and of lengths 1 and 2). The
+// candidates left at the end are checked at each of huge_primes too
+pub fn search_z4_opt(height_max: i64, height_min: i64, prime_power: u32, bounds: bool, huge_primes: &[i64]) {
     let bmin = ((height_max as f32).sqrt().sqrt() / 2.0).floor() as i64;
-    (1..=bmin).into_par_iter().for_each(|b| {
-        println!("{}", b);
-        for a in -height_max..=height_max {
-            let b = 2*b;
-            let b = b*b*b*b;
+    let admissible = real_cycle_parameters(4, 2);
+    let contexts = prime_contexts();
+    let before_refinement = AtomicUsize::new(0);
+    let after_refinement = AtomicUsize::new(0);
+    // The wheel for each denominator, built by whichever tile needs it first
+    // Only built once there's a candidate to check against it
+    let bifurcations: OnceLock<BifurcationLocus> = OnceLock::new();
+    let wheels: Vec<OnceLock<ResidueWheel>> = (1..=bmin).map(|_| OnceLock::new()).collect();
+    run_tiles(z4_tiles(bmin, height_max), |tile| {
+        let b = 2*tile.k;
+        let b = b*b*b*b;
+        // Only numerators that survive the table lookups
+        // at the primes of the wheel are worth looking at
+        let wheel = wheels[tile.k as usize - 1]
+            .get_or_init(|| ResidueWheel::z4c(b, 2, (2*height_max + 1).min(MAX_WHEEL)));
+        let candidates: Vec<Rational> = wheel.numerators(tile.lo, tile.hi).filter_map(|a| {
+            if a <= height_min && b <= height_min {
+                return None;
+            }
+            // Rational::new reduces, so a/b was in lowest
+            // terms exactly when the denominator is unchanged
+            let c = Rational::new(a, b);
+            if c.denom != b || !may_have_real_cycles(&admissible, c) {
+                return None;
+            }
+            Some(c)
+        }).collect();
+
+        for block in candidates.chunks(Z4_BATCH_SIZE) {
+            for (i, possibilities) in z4c_possible_periods_batch(block, 2, contexts) {
+                let c = block[i];
+                let possibilities = real_periods_filter(4, c, &possibilities);
+                if possibilities.is_empty() {
+                    continue;
+                }
+                let possibilities = if prime_power > 1 {
+                    before_refinement.fetch_add(1, AtomicOrdering::Relaxed);
+                    let refined = z4c_prime_power_refinement(c, &possibilities, prime_power);
+                    if refined.is_empty() {
+                        continue;
+                    }
+                    after_refinement.fetch_add(1, AtomicOrdering::Relaxed);
+                    refined
+                } else {
+                    possibilities
+                };
+                let (possibilities, eliminated) = bad_prime_filter(4, c, &possibilities);
+                for (p, periods) in eliminated {
+                    println!("Ruled out periods {:?} for {} at the bad prime {}", periods, c, p);
+                }
+                let f = PolynomialInQ::from(vec![
+                    Rational::one(), Rational::zero(), Rational::zero(), Rational::zero(), c
+                ]);
+                let possibilities = huge_prime_refinement(&f, &possibilities, huge_primes);
+                if !possibilities.is_empty() {
+                    for n in possibilities.iter() {
+                        for cycle in rational_cycles(&f, n) {
+                            println!("{}", format_found_cycle(c, &cycle));
+                        }
+                    }
+                    if bounds {
+                        let mut periods = possibilities.clone();
+                        periods.extend(1..=2);
+                        println!("Bounds for {}: {}", c, periodic_point_bound(&f, &periods));
+                    }
+                    println!("{}", format_search_result(c, possibilities));
+                    let locus = bifurcations.get_or_init(|| BifurcationLocus::new(4, Z4_BIFURCATION_PERIODS));
+                    let periods = locus.periods_at(c);
+                    if !periods.is_empty() {
+                        println!("{} is a bifurcation parameter for periods {:?}", c, periods);
+                    }
+                }
+            }
+        }
+    });
+    if prime_power > 1 {
+        println!("Reduction mod p^{} ruled out {} of {} remaining candidates",
+                 prime_power,
+                 before_refinement.load(AtomicOrdering::Relaxed) - after_refinement.load(AtomicOrdering::Relaxed),
+                 before_refinement.load(AtomicOrdering::Relaxed));
+    }
+    println!("Completed search!");
+}
+
+// Search for c whose reductions leave room for all the cycles of
+// config at once, after the same real and bad prime filters as
+// search_z4_opt (for config's lengths). When c has odd denominator,
+// z^4 + c mod 2 is z or z + 1 with multipliers 0, so its cycles over Q
+// have length 1 or 2; search_z4_opt can skip those c, but here they're
+// the only ones a configuration of fixed points and 2-cycles needs
+pub fn search_z4_configuration(height_max: i64, height_min: i64, config: &Configuration) {
+    let rmax = (height_max as f32).sqrt().sqrt().floor() as i64;
+    let roots: Vec<i64> = if config.longest() <= 2 {
+        (1..=rmax).collect()
+    } else {
+        (2..=rmax).step_by(2).collect()
+    };
+    let admissible = real_cycle_parameters(4, config.longest() - 1);
+    let lengths = config.periods();
+    let contexts = prime_contexts();
+    let wheels: Vec<OnceLock<ResidueWheel>> = roots.iter().map(|_| OnceLock::new()).collect();
+    let found = AtomicUsize::new(0);
+    println!("Looking for c with {}", config);
+    run_tiles(z4_tiles(roots.len() as i64, height_max), |tile| {
+        let r = roots[tile.k as usize - 1];
+        let b = r*r*r*r;
+        let wheel = wheels[tile.k as usize - 1].get_or_init(|| {
+            ResidueWheel::z4c_keeping(b, (2*height_max + 1).min(MAX_WHEEL), |periods| config.allowed_by(periods))
+        });
+        for a in wheel.numerators(tile.lo, tile.hi) {
             if a <= height_min && b <= height_min {
                 continue;
             }
-            let flo:f32 = (a as f32) / (b as f32);
-            if flo > -0.913942 {
+            let c = Rational::new(a, b);
+            if c.denom != b || !may_have_real_cycles(&admissible, c) {
                 continue;
             }
-            let g = a.gcd(&b);
-            if g != 1 && g != -1 {
+            if !config.allowed_by(&real_periods_filter(4, c, &lengths))
+                || !config.allowed_by(&bad_prime_filter(4, c, &lengths).0) {
                 continue;
             }
-            
-            let c = Rational::new(a, b);
-
-            let res = z4c_possible_periods_search(c, 2);
-
-            if let Some(possibilities) = res {
-                println!("{}", format_search_result(c, possibilities));
+            if z4c_admits(c, config, contexts) {
+                found.fetch_add(1, AtomicOrdering::Relaxed);
+                println!("Check {}, which might have {}", c, config);
             }
         }
     });
+    println!("{} values of c might have {}", found.load(AtomicOrdering::Relaxed), config);
     println!("Completed search!");
 }
 
 pub fn search_z3_opt(height_max: i64, _height_min: i64) {
     let bmax = ((height_max as f32).cbrt()).floor() as i64;
-    (-height_max..=height_max).into_par_iter().for_each(|num_a| {
-            // println!("{}", num_a);
-            for num_b in -height_max..=height_max {
+    let contexts = prime_contexts();
+    run_tiles(z3_tiles(height_max), |tile| {
+        for num_a in tile.num_a.0..=tile.num_a.1 {
+            for num_b in tile.num_b.0..=tile.num_b.1 {
                 for denom_a in -bmax..=bmax {
                     for denom_b in -bmax..=bmax {
                         let denom1 = EisensteinInteger::new(denom_a, denom_b);
@@ -78,21 +218,30 @@ pub fn search_z3_opt(height_max: i64, _height_min: i64) {
                             continue;
                         }
                         let numer = EisensteinInteger::new(num_a, num_b);
-                        let c = QwElement::new(numer, denom);
                         if denom.is_zero() || numer.is_zero() {
                             continue;
                         }
                         if !numer.gcd(&denom).is_unit() {
                             continue;
                         }
+                        let c = QwElement::new(numer, denom);
 
-                        if let Some(set) = z3c_possible_periods_search(c, 2) {
+                        if let Some(set) = z3c_possible_periods_search(c, 2, contexts) {
+                            let f = PolynomialInQw::from(vec![
+                                QwElement::one(), QwElement::zero(), QwElement::zero(), c
+                            ]);
+                            for n in set.iter() {
+                                for cycle in qw_cycles(&f, n) {
+                                    println!("{}", format_found_cycle(c, &cycle));
+                                }
+                            }
                             println!("{}", format_search_result(c, set));
                         }
                     }
                 }
             }
-        });
+        }
+    });
 }
 
 // Setup the command line interface
@@ -109,6 +258,64 @@ fn main() {
                         .help("Minimum height of c values to check")
                         .takes_value(true)
                         .default_value("0");
+    let prime_power_arg = Arg::with_name("prime_power")
+                        .short("k")
+                        .long("prime_power")
+                        .help("Also rule out periods using reductions mod p^k")
+                        .takes_value(true)
+                        .default_value("1");
+    let threads_arg = Arg::with_name("threads")
+                        .short("t")
+                        .long("threads")
+                        .help("Number of worker threads (0 for one per core)")
+                        .takes_value(true)
+                        .default_value("0");
+    let coeffs_arg = Arg::with_name("coeffs")
+                        .short("f")
+                        .long("coeffs")
+                        .help("Coefficients of the polynomial, highest degree first (e.g. 1,0,-29/16)")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .required(true);
+    let period_arg = Arg::with_name("period")
+                        .short("n")
+                        .long("period")
+                        .help("Which dynatomic polynomial to factor")
+                        .takes_value(true)
+                        .required(true);
+    let prime_max_arg = Arg::with_name("prime_max")
+                        .short("p")
+                        .long("prime_max")
+                        .help("Largest prime to reduce mod")
+                        .takes_value(true)
+                        .default_value("1000");
+    let param_arg = Arg::with_name("param")
+                        .short("c")
+                        .long("param")
+                        .help("The c in z^4 + c (e.g. -29/16)")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .required(true);
+    let prime_arg = Arg::with_name("prime")
+                        .short("p")
+                        .long("prime")
+                        .help("Prime to reduce mod")
+                        .takes_value(true)
+                        .required(true);
+    let bounds_arg = Arg::with_name("bounds")
+                        .short("b")
+                        .long("bounds")
+                        .help("Also bound the number of rational cycles of each candidate");
+    let huge_primes_arg = Arg::with_name("huge_primes")
+                        .long("huge_primes")
+                        .help("Also check the remaining candidates mod these primes (e.g. 1000000007,998244353)")
+                        .takes_value(true);
+    let configuration_arg = Arg::with_name("configuration")
+                        .short("j")
+                        .long("configuration")
+                        .help("Instead look for c which might have all these cycle lengths at once (e.g. 2,3)")
+                        .takes_value(true)
+                        .conflicts_with_all(&["prime_power", "bounds", "huge_primes"]);
     let matches = App::new("Large Period Searcher")
             .version("0.1")
             .author("Matt Torrence <torrma01@gettysburg.edu>")
@@ -116,11 +323,26 @@ fn main() {
             .subcommand(SubCommand::with_name("z4c")
                 .about("Search z^4 + c with standard optimizations / reductions")
                 .arg(hmax_arg.clone())
-                .arg(hmin_arg.clone()))
+                .arg(hmin_arg.clone())
+                .arg(threads_arg.clone())
+                .arg(prime_power_arg)
+                .arg(bounds_arg)
+                .arg(huge_primes_arg)
+                .arg(configuration_arg))
             .subcommand(SubCommand::with_name("z3c")
                 .about("Search z^3 + c over Q(w) with standard optimizations / reductions")
                 .arg(hmax_arg)
-                .arg(hmin_arg))
+                .arg(hmin_arg)
+                .arg(threads_arg))
+            .subcommand(SubCommand::with_name("cycletypes")
+                .about("Factorization patterns of a dynatomic polynomial mod many primes")
+                .arg(coeffs_arg)
+                .arg(period_arg)
+                .arg(prime_max_arg))
+            .subcommand(SubCommand::with_name("structure")
+                .about("Cycles, multipliers and tails of z^4 + c mod a prime")
+                .arg(param_arg)
+                .arg(prime_arg))
             .get_matches();
     
     if let Some(matches) = matches.subcommand_matches("z4c") {
@@ -134,7 +356,34 @@ fn main() {
                             .unwrap()
                             .parse()
                             .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
-        search_z4_opt(hmax, hmin);
+        let k: u32 = matches
+                            .value_of("prime_power")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        let threads: usize = matches
+                            .value_of("threads")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        set_threads(threads);
+        if let Some(config) = matches.value_of("configuration") {
+            let config: Configuration = config
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected cycle lengths: {}", a));
+            search_z4_configuration(hmax, hmin, &config);
+        } else {
+            let huge_primes: Vec<i64> = matches
+                            .value_of("huge_primes")
+                            .map_or(vec![], |ps| ps.split(',').map(|p| {
+                                let p: i64 = p.parse().unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+                                if !(2..1 << 32).contains(&p) || !math::small_prime(p as usize) {
+                                    panic!("Expected a prime below 2^32, got {}", p);
+                                }
+                                p
+                            }).collect());
+            search_z4_opt(hmax, hmin, k, matches.is_present("bounds"), &huge_primes);
+        }
     }
     if let Some(matches) = matches.subcommand_matches("z3c") {
         let hmax: i64 = matches
@@ -147,8 +396,55 @@ fn main() {
                             .unwrap()
                             .parse()
                             .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        let threads: usize = matches
+                            .value_of("threads")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        set_threads(threads);
         search_z3_opt(hmax, hmin);
     }
+    if let Some(matches) = matches.subcommand_matches("cycletypes") {
+        let coeffs: Vec<Rational> = matches
+                            .value_of("coeffs")
+                            .unwrap()
+                            .split(',')
+                            .map(|c| c.parse().unwrap_or_else(|e| panic!("Error parsing, expected rational: {}", e)))
+                            .collect();
+        let n: usize = matches
+                            .value_of("period")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        let prime_max: usize = matches
+                            .value_of("prime_max")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        let f = PolynomialInQ::from(coeffs);
+        println!("{}", cycle_type_statistics(&f, n, prime_max));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("structure") {
+        let c: Rational = matches
+                            .value_of("param")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|e| panic!("Error parsing, expected rational: {}", e));
+        let p: usize = matches
+                            .value_of("prime")
+                            .unwrap()
+                            .parse()
+                            .unwrap_or_else(|a| panic!("Error parsing, expected integer: {}", a));
+        if !math::small_prime(p) || p as i64 > MAX_STRUCTURE_PRIME {
+            panic!("Expected a prime up to {}, got {}", MAX_STRUCTURE_PRIME, p);
+        }
+        if c.denom % p as i64 == 0 {
+            panic!("z^4 + {} has bad reduction mod {}", c, p);
+        }
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::zero(), Rational::zero(), c]);
+        print!("{}", CycleStructure::new(&f.do_reduction(p)));
+    }
 }
 
 
@@ -156,18 +452,20 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::math::*;
+    use num_integer::Integer;
     use test::Bencher;
     use test::black_box;
 
     #[test]
     fn test_functionality() {
         let mut output = "".to_string();
-        (-100_000..=100_000i64).into_iter().for_each(|a| {
+        let admissible = real_cycle_parameters(2, 2);
+        (-100_000..=100_000i64).for_each(|a| {
             for b in 1..=60i64 {
                 let b = b*b;
-                // Analytic bound (should be above or below gcd?)
-                let flo:f32 = (a as f32) / (b as f32);
-                if flo > -0.913942 {
+                // Analytic bound
+                if !may_have_real_cycles(&admissible, Rational::new(a, b)) {
                     continue;
                 }
                 let g = a.gcd(&b);
@@ -222,17 +520,17 @@ Manually check -29/16
 
             let res = possible_periods_search(fc, 2);
 
-            assert!(res.unwrap().contains(&3));
+            assert!(res.unwrap().contains(3));
         });
     }
 
     #[bench]
     fn bench_faster_conditions(b: &mut Bencher) {
+        let admissible = real_cycle_parameters(4, 2);
         b.iter(|| {
             let a = -5649488755i64;
             let b = 639128961i64;
-            let flo:f32 = (a as f32) / (b as f32);
-            if flo > -0.913942 {
+            if !may_have_real_cycles(&admissible, Rational::new(a, b)) {
                 panic!();
             }
             let g = a.gcd(&b);
@@ -242,7 +540,7 @@ Manually check -29/16
             black_box(g);
 
             let a = -5649488753i64;
-            if flo > -0.913942 {
+            if !may_have_real_cycles(&admissible, Rational::new(a, b)) {
                 panic!();
             }
             let g = a.gcd(&b);
@@ -257,23 +555,64 @@ Manually check -29/16
     fn bench_faster_search(b: &mut Bencher) {
         b.iter(|| {
 
-            let res = z4c_possible_periods_search(Rational::new(-5649488755,639128961), 1);
+            let res = z4c_possible_periods_search(Rational::new(-5649488755,639128961), 1, prime_contexts());
             
-            assert!(!res.is_none());
-            assert!(res.unwrap().contains(&2));
+            assert!(res.is_some());
+            assert!(res.unwrap().contains(2));
 
-            let res = z4c_possible_periods_search(Rational::new(-5649488753,639128961), 1);
+            let res = z4c_possible_periods_search(Rational::new(-5649488753,639128961), 1, prime_contexts());
 
             assert!(res.is_none());
         })
     }
 
+    fn z4c_bench_block() -> Vec<Rational> {
+        let b = 10i64.pow(4);
+        (-5_000_000..-5_000_000 + 4 * Z4_BATCH_SIZE as i64)
+            .filter(|a| a.gcd(&b) == 1)
+            .map(|a| Rational::new(a, b))
+            .collect()
+    }
+
+    #[bench]
+    fn bench_z4c_single(ben: &mut Bencher) {
+        let cs = z4c_bench_block();
+        let contexts = prime_contexts();
+        ben.iter(|| {
+            cs.iter().filter(|&&c| z4c_possible_periods_search(c, 2, contexts).is_some()).count()
+        });
+    }
+
+    #[bench]
+    fn bench_z4c_batched(ben: &mut Bencher) {
+        let cs = z4c_bench_block();
+        let contexts = prime_contexts();
+        ben.iter(|| {
+            z4c_possible_periods_batch(&cs, 2, contexts).len()
+        });
+    }
+
+    // A prime past what fits in cache as 16-byte entries
+    const BENCH_GRAPH_PRIME: i64 = 1_000_003;
+
+    #[bench]
+    fn bench_functional_graph_table(ben: &mut Bencher) {
+        let f = Polynomial::new(vec![1, 0, 0, 0, 5], Some(BENCH_GRAPH_PRIME));
+        ben.iter(|| table_possible_periods(&f, 1).len());
+    }
+
+    #[bench]
+    fn bench_functional_graph_compact(ben: &mut Bencher) {
+        ben.iter(|| fast_possible_periods(Polynomial::new(vec![1, 0, 0, 0, 5], Some(BENCH_GRAPH_PRIME))).len());
+    }
+
     #[bench]
     fn bench_conditions(ben: &mut Bencher) {
-        let (a, b) = (-3749999571i128, 3906250000i128);
+        // Just past where z^4 + c gets real 4-cycles, about -1.1196
+        let (a, b) = (-4374999569i128, 3906250000i128);
+        let admissible = real_cycle_parameters(4, 2);
         ben.iter(|| {
-            let flo:f32 = (a as f32) / (b as f32);
-            if flo > -0.913942 {
+            if !may_have_real_cycles(&admissible, Rational::new(a as i64, b as i64)) {
                 panic!();
             }
             let g = a.gcd(&b);
@@ -332,12 +671,12 @@ Manually check -29/16
     fn bench_search_speed_test(b: &mut Bencher) {
         b.iter(|| {
             let mut output = "".to_string();
-            (-3_000..=3_000i64).into_iter().for_each(|a| {
+            let admissible = real_cycle_parameters(2, 2);
+            (-3_000..=3_000i64).for_each(|a| {
                 for b in 1..=26i64 {
                     let b = b*b;
-                    // Random Analytic bound (doesn't actually apply for z^2 case?)
-                    let flo:f32 = (a as f32) / (b as f32);
-                    if flo > -0.913942 {
+                    // Analytic bound
+                    if !may_have_real_cycles(&admissible, Rational::new(a, b)) {
                         continue;
                     }
                     let g = a.gcd(&b);
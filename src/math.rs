@@ -1,4 +1,5 @@
 use num_integer::{Integer};
+use std::ops::{Add, Sub, Mul, Neg};
 
 pub fn mod_inverse<T: Integer + Copy>(num: T, prime: T) -> T {
     let mut a : T = prime;
@@ -22,24 +23,212 @@ pub fn mod_inverse<T: Integer + Copy>(num: T, prime: T) -> T {
     }
 }
 
+// Arithmetic mod m, for 0 < m < 2^63. Below 2^32 products of
+// residues fit in a u64 and are reduced with Barrett's method
+// (a multiply-high and a subtraction instead of a division);
+// above, they're formed and reduced in a u128
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modulus {
+    m: u64,
+    // floor(2^64 / m) when m < 2^32, otherwise 0
+    mu: u128,
+}
+
 #[allow(unused)]
-pub fn multiplicative_order<T: Integer + Copy>(a: T, p: T) -> u32 {
-    let mut res = 1;
-    let mut curr = a;
-    debug_assert!(a > T::zero());
-    while curr != T::one() {
-        curr = curr * a;
-        curr = curr % p;
-        res += 1;
+impl Modulus {
+    pub fn new(m: i64) -> Modulus {
+        assert!(m > 0, "Modulus must be positive");
+        let m = m as u64;
+        let mu = if m < 1 << 32 { (1u128 << 64) / m as u128 } else { 0 };
+        Modulus { m, mu }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.m as i64
+    }
+
+    #[inline]
+    pub fn reduce(&self, a: i64) -> u64 {
+        if a >= 0 && (a as u64) < self.m {
+            a as u64
+        } else if self.mu != 0 {
+            let r = self.barrett(a.unsigned_abs());
+            if a < 0 && r != 0 { self.m - r } else { r }
+        } else {
+            a.rem_euclid(self.m as i64) as u64
+        }
+    }
+
+    // x mod m for any x < 2^64, when m < 2^32: the quotient estimate
+    // x mu / 2^64 is below x / m by less than x / 2^64 < 1
+    #[inline]
+    fn barrett(&self, x: u64) -> u64 {
+        let q = ((x as u128 * self.mu) >> 64) as u64;
+        let mut r = x - q * self.m;
+        while r >= self.m {
+            r -= self.m;
+        }
+        r
+    }
+
+    #[inline]
+    pub fn mul(&self, a: u64, b: u64) -> u64 {
+        if self.mu != 0 {
+            self.barrett(a * b)
+        } else {
+            ((a as u128 * b as u128) % self.m as u128) as u64
+        }
+    }
+
+    #[inline]
+    pub fn add(&self, a: u64, b: u64) -> u64 {
+        let s = a + b;
+        if s >= self.m { s - self.m } else { s }
+    }
+
+    #[inline]
+    pub fn sub(&self, a: u64, b: u64) -> u64 {
+        if a >= b { a - b } else { a + self.m - b }
+    }
+
+    // a^e by repeated squaring
+    pub fn pow(&self, a: u64, e: u64) -> u64 {
+        let mut res = self.reduce(1);
+        let mut base = a;
+        let mut e = e;
+        while e > 0 {
+            if e & 1 == 1 {
+                res = self.mul(res, base);
+            }
+            base = self.mul(base, base);
+            e >>= 1;
+        }
+        res
+    }
+
+    pub fn inverse(&self, a: u64) -> Option<u64> {
+        let m = self.m as i64;
+        let a = a as i64;
+        if a.gcd(&m) != 1 {
+            return None;
+        }
+        Some(mod_inverse(a, m) as u64 % self.m)
+    }
+
+    pub fn int(&self, a: i64) -> ModInt {
+        ModInt { value: self.reduce(a), modulus: *self }
+    }
+
+    // As int, for a residue that's already reduced
+    #[inline]
+    pub fn residue(&self, a: u64) -> ModInt {
+        debug_assert!(a < self.m);
+        ModInt { value: a, modulus: *self }
     }
-    res
+}
+
+// A residue together with its modulus, for when
+// writing the arithmetic out with operators is clearer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModInt {
+    value: u64,
+    modulus: Modulus,
+}
+
+#[allow(unused)]
+impl ModInt {
+    pub fn value(&self) -> i64 {
+        self.value as i64
+    }
+
+    #[inline]
+    pub fn residue(&self) -> u64 {
+        self.value
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.value == 0
+    }
+
+    pub fn pow(&self, e: u64) -> ModInt {
+        ModInt { value: self.modulus.pow(self.value, e), modulus: self.modulus }
+    }
+
+    pub fn inverse(&self) -> Option<ModInt> {
+        Some(ModInt { value: self.modulus.inverse(self.value)?, modulus: self.modulus })
+    }
+}
+
+impl Add for ModInt {
+    type Output = ModInt;
+
+    #[inline]
+    fn add(self, other: ModInt) -> ModInt {
+        debug_assert_eq!(self.modulus, other.modulus);
+        ModInt { value: self.modulus.add(self.value, other.value), modulus: self.modulus }
+    }
+}
+
+impl Sub for ModInt {
+    type Output = ModInt;
+
+    #[inline]
+    fn sub(self, other: ModInt) -> ModInt {
+        debug_assert_eq!(self.modulus, other.modulus);
+        ModInt { value: self.modulus.sub(self.value, other.value), modulus: self.modulus }
+    }
+}
+
+impl Mul for ModInt {
+    type Output = ModInt;
+
+    #[inline]
+    fn mul(self, other: ModInt) -> ModInt {
+        debug_assert_eq!(self.modulus, other.modulus);
+        ModInt { value: self.modulus.mul(self.value, other.value), modulus: self.modulus }
+    }
+}
+
+impl Neg for ModInt {
+    type Output = ModInt;
+
+    #[inline]
+    fn neg(self) -> ModInt {
+        ModInt { value: self.modulus.sub(0, self.value), modulus: self.modulus }
+    }
+}
+
+// The order of a in F_p^*: start from p - 1 and remove
+// each prime factor q for as long as a^(order/q) is still 1
+#[allow(unused)]
+pub fn multiplicative_order(a: i64, p: i64) -> u64 {
+    let a = Modulus::new(p).int(a);
+    debug_assert!(!a.is_zero());
+    let mut order = (p - 1) as u64;
+    for (q, _) in factorize(p - 1) {
+        let q = q as u64;
+        while order.is_multiple_of(q) && a.pow(order / q).value() == 1 {
+            order /= q;
+        }
+    }
+    order
+}
+
+// The smallest generator of F_p^*
+#[allow(unused)]
+pub fn primitive_root(p: i64) -> i64 {
+    let m = Modulus::new(p);
+    let factors = factorize(p - 1);
+    (1..p).find(|&g| {
+        factors.iter().all(|&(q, _)| m.int(g).pow(((p - 1) / q) as u64).value() != 1)
+    }).unwrap()
 }
 
 lazy_static! {
     static ref PRIMES: [bool; 301] = {
         let mut p = [false; 301];
-        for i in 0..301 {
-            p[i] = small_prime(i);
+        for (i, is_prime) in p.iter_mut().enumerate() {
+            *is_prime = small_prime(i);
         }
         p
     };
@@ -51,12 +240,7 @@ pub fn prime(n: usize) -> bool {
 }
 
 pub fn small_prime(n: usize) -> bool {
-    for x in 2..=(n as f32).sqrt().ceil() as usize {
-        if n % x == 0 {
-            return false;
-        }
-    }
-    return true;
+    n >= 2 && (2..).take_while(|x| x * x <= n).all(|x| !n.is_multiple_of(x))
 }
 
 const fn num_bits<T>() -> usize { std::mem::size_of::<T>() * 8 }
@@ -68,24 +252,13 @@ pub fn log_2(x: i64) -> u32 {
 
 // Return a^b mod p
 pub fn mod_power(a: i64, b: i64, p: i64) -> i64 {
-    let mut res = 1i64;
-    for _ in 0..b {
-        let r = res.checked_mul(a);
-        match r {
-            Some(val) => res = val,
-            None => {
-                res %= p;
-                res *= a;
-            }
-        }
-    }
-    res.rem_euclid(p)
+    Modulus::new(p).int(a).pow(b as u64).value()
 }
 
-fn cipolla_mult(ab: (i64, i64), cd: (i64, i64), w: i64, p: i64) -> (i64, i64) {
+fn cipolla_mult(ab: (ModInt, ModInt), cd: (ModInt, ModInt), w: ModInt) -> (ModInt, ModInt) {
     let (a, b) = ab;
     let (c, d) = cd;
-    ((a*c + b*d*w)%p, (a*d + b*c)%p)
+    (a*c + b*d*w, a*d + b*c)
 }
 
 // Take in an integer n and odd prime p
@@ -93,45 +266,120 @@ fn cipolla_mult(ab: (i64, i64), cd: (i64, i64), w: i64, p: i64) -> (i64, i64) {
 // or None if no roots exist
 pub fn cipolla(n: i64, p: i64) -> Option<(i64, i64)> {
     debug_assert!(p > 1);
-    let n = n.rem_euclid(p);
-    if n == 0 || n == 1 {
-        return Some((n, (-n).rem_euclid(p)));
+    let m = Modulus::new(p);
+    let n = m.int(n);
+    if n.value() <= 1 {
+        return Some((n.value(), (-n).value()));
     }
-    let phi = p - 1;
-    if mod_power(n, phi/2, p) != 1 {
+    let phi = (p - 1) as u64;
+    if n.pow(phi / 2).value() != 1 {
         return None;
     }
     if p % 4 == 3 {
-        let ans = mod_power(n, (p + 1)/4, p);
-        return Some((ans, (-ans).rem_euclid(p)));
-    }
-    let mut aa = 0;
-    for i in 1..p {
-        let temp = mod_power((i*i - n)%p, phi/2, p);
-        if temp == phi {
-            aa = i;
-            break;
-        }
+        let ans = n.pow((p as u64 + 1) / 4);
+        return Some((ans.value(), (-ans).value()));
     }
+    // a with a^2 - n a non-residue, so F_p(sqrt w) is F_p^2
+    let a = (1..p).map(|i| m.int(i))
+        .find(|&a| (a * a - n).pow(phi / 2) == -m.int(1))
+        .unwrap();
+    let w = a * a - n;
     let exponent = (p + 1)/2;
-    let mut x1 = (aa, 1);
-    let mut x2 = cipolla_mult(x1, x1, aa*aa - n, p);
+    let mut x1 = (a, m.int(1));
+    let mut x2 = cipolla_mult(x1, x1, w);
     let l = log_2(exponent);
     for i in (0..l).rev() {
         if (exponent & (1 << i)) == 0 {
-            x2 = cipolla_mult(x2, x1, aa*aa-n, p);
-            x1 = cipolla_mult(x1, x1, aa*aa-n, p); 
+            x2 = cipolla_mult(x2, x1, w);
+            x1 = cipolla_mult(x1, x1, w);
         } else {
-            x1 = cipolla_mult(x1, x2, aa*aa-n, p);
-            x2 = cipolla_mult(x2, x2, aa*aa-n, p);
+            x1 = cipolla_mult(x1, x2, w);
+            x2 = cipolla_mult(x2, x2, w);
         }
     }
-    Some((x1.0, (-x1.0).rem_euclid(p)))
+    let x = x1.0;
+    Some((x.value(), (-x).value()))
 }
 
-// Check if -3 is a quadratic residue mod p
+// Check if there is a homomorphism Z[w] -> F_p, i.e. if p
+// is 3 or -3 is a quadratic residue mod p (p = 1 mod 3).
+// The prime 2 is inert, with residue field F_4.
 pub fn has_qw_homomorphism(p: i64) -> bool {
-    p == 3 || mod_power(p-3, (p - 1)/2, p) == 1
+    p == 3 || p % 3 == 1
+}
+
+// The images of w under the homomorphisms Z[w] -> F_p: the two
+// roots of x^2 + x + 1 when p = 1 mod 3, only 1 when p = 3 (which
+// ramifies, 3 = -w^2 (1 - w)^2), and none otherwise
+pub fn qw_homomorphisms(p: i64) -> Vec<i64> {
+    let mut res = Vec::with_capacity(2);
+    if p == 3 {
+        res.push(1);
+    } else if has_qw_homomorphism(p) {
+        let (w1, w2) = cube_roots_of_unity(p).unwrap();
+        res.push(w1);
+        res.push(w2);
+    }
+    res
+}
+
+// Return the two roots of x^2 + x + 1 mod an odd prime p,
+// i.e. the possible images of w in F_p, or None if
+// p = 2 mod 3 (in which case p is inert in Z[w])
+pub fn cube_roots_of_unity(p: i64) -> Option<(i64, i64)> {
+    debug_assert!(p > 2);
+    let m = Modulus::new(p);
+    let half = m.int(2).inverse().unwrap();
+    let (s1, s2) = cipolla(p - 3, p)?;
+    let w = |s: i64| ((m.int(s) - m.int(1)) * half).value();
+    Some((w(s1), w(s2)))
+}
+
+// The exponent of p in n != 0
+pub fn valuation(n: i64, p: i64) -> u32 {
+    debug_assert!(n != 0);
+    let mut n = n;
+    let mut v = 0;
+    while n % p == 0 {
+        n /= p;
+        v += 1;
+    }
+    v
+}
+
+// Factor |n| by trial division, returning (prime, exponent)
+// pairs in increasing order of the prime
+pub fn factorize(n: i64) -> Vec<(i64, u32)> {
+    let mut n = n.abs();
+    let mut res = Vec::new();
+    let mut d = 2;
+    while d * d <= n {
+        if n % d == 0 {
+            let mut e = 0;
+            while n % d == 0 {
+                n /= d;
+                e += 1;
+            }
+            res.push((d, e));
+        }
+        d += if d == 2 { 1 } else { 2 };
+    }
+    if n > 1 {
+        res.push((n, 1));
+    }
+    res
+}
+
+// The Mobius function of n >= 1
+pub fn mobius(n: usize) -> i32 {
+    let factors = factorize(n as i64);
+    if factors.iter().any(|&(_, e)| e > 1) {
+        0
+    } else if factors.len().is_multiple_of(2) {
+        1
+    } else {
+        -1
+    }
 }
 
 #[cfg(test)]
@@ -153,4 +401,56 @@ mod tests {
 
         println!("{:?}", cipolla(0, 3));
     }
+
+    #[test]
+    fn test_modular_arithmetic() {
+        // Small moduli go through Barrett reduction, large ones through u128
+        for &p in &[2i64, 3, 101, 65_537, 1_000_000_007, 4_294_967_311, 2_305_843_009_213_693_951] {
+            let m = Modulus::new(p);
+            let a = m.reduce(-12_345_678_901);
+            assert_eq!(m.mul(a, m.inverse(a).unwrap()), 1 % m.value() as u64);
+            assert_eq!(mod_power(a as i64, p - 1, p), 1);
+            assert_eq!((m.int(-1) * m.int(-1)).value(), 1);
+        }
+        // 2 has order 61 mod 2^61 - 1
+        assert_eq!(mod_power(2, 61, 2_305_843_009_213_693_951), 1);
+        assert_eq!(Modulus::new(12).inverse(4), None);
+
+        for &p in &[7i64, 101, 10_007, 1_000_000_007] {
+            let g = primitive_root(p);
+            assert_eq!(multiplicative_order(g, p) as i64, p - 1);
+            // g^k has order (p - 1) / gcd(k, p - 1)
+            assert_eq!(multiplicative_order(mod_power(g, 6, p), p) as i64, (p - 1) / 6i64.gcd(&(p - 1)));
+        }
+        assert_eq!(primitive_root(7), 3);
+        assert_eq!(multiplicative_order(1, 13), 1);
+        assert_eq!(multiplicative_order(12, 13), 2);
+    }
+
+    #[test]
+    fn test_factorize() {
+        assert!(small_prime(2) && small_prime(3) && !small_prime(4) && !small_prime(1) && !small_prime(0));
+        assert_eq!((0..30).filter(|&n| prime(n)).count(), 10);
+        assert_eq!(factorize(1), vec![]);
+        assert_eq!(factorize(-360), vec![(2, 3), (3, 2), (5, 1)]);
+        assert_eq!(factorize(10007), vec![(10007, 1)]);
+        assert_eq!(cube_roots_of_unity(5), None);
+        let (w1, w2) = cube_roots_of_unity(7).unwrap();
+        assert_eq!((w1 * w1 + w1 + 1) % 7, 0);
+        assert_eq!((w2 * w2 + w2 + 1) % 7, 0);
+        assert_ne!(w1, w2);
+    }
+
+    #[test]
+    fn test_qw_homomorphisms() {
+        assert!(qw_homomorphisms(2).is_empty());
+        assert!(qw_homomorphisms(5).is_empty());
+        assert_eq!(qw_homomorphisms(3).as_slice(), &[1]);
+        assert_eq!(qw_homomorphisms(7).len(), 2);
+        for p in 2..300 {
+            if prime(p as usize) {
+                assert_eq!(has_qw_homomorphism(p), !qw_homomorphisms(p).is_empty());
+            }
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,152 @@
+// Sets of periods, as a bitset of the small periods (almost all of
+// them) with a sorted list for the rest. Intersections are in place
+// and "is anything left above the goal" is a few word operations,
+// which is most of what the searches do with them
+
+use std::fmt;
+use std::iter::FromIterator;
+
+const WORDS: usize = 4;
+const BITS: usize = 64 * WORDS;
+
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct PeriodSet {
+    words: [u64; WORDS],
+    // Periods >= BITS, sorted and without repeats
+    overflow: Vec<usize>,
+}
+
+#[allow(unused)]
+impl PeriodSet {
+    pub fn new() -> PeriodSet {
+        PeriodSet::default()
+    }
+
+    pub fn insert(&mut self, n: usize) {
+        if n < BITS {
+            self.words[n / 64] |= 1 << (n % 64);
+        } else if let Err(i) = self.overflow.binary_search(&n) {
+            self.overflow.insert(i, n);
+        }
+    }
+
+    pub fn contains(&self, n: usize) -> bool {
+        if n < BITS {
+            self.words[n / 64] & (1 << (n % 64)) != 0
+        } else {
+            self.overflow.binary_search(&n).is_ok()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum::<usize>() + self.overflow.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0) && self.overflow.is_empty()
+    }
+
+    pub fn intersect_with(&mut self, other: &PeriodSet) {
+        for (w, o) in self.words.iter_mut().zip(other.words.iter()) {
+            *w &= o;
+        }
+        if !self.overflow.is_empty() {
+            self.overflow.retain(|n| other.overflow.binary_search(n).is_ok());
+        }
+    }
+
+    // As intersect_with, but only allocating for what's left
+    pub fn intersection(&self, other: &PeriodSet) -> PeriodSet {
+        let mut words = self.words;
+        for (w, o) in words.iter_mut().zip(other.words.iter()) {
+            *w &= o;
+        }
+        let overflow = self.overflow.iter().copied()
+            .filter(|n| other.overflow.binary_search(n).is_ok())
+            .collect();
+        PeriodSet { words, overflow }
+    }
+
+    pub fn is_subset(&self, other: &PeriodSet) -> bool {
+        self.words.iter().zip(other.words.iter()).all(|(w, o)| w & !o == 0)
+            && self.overflow.iter().all(|n| other.overflow.binary_search(n).is_ok())
+    }
+
+    // Is there any period > goal?
+    pub fn any_above(&self, goal: usize) -> bool {
+        if !self.overflow.is_empty() && *self.overflow.last().unwrap() > goal {
+            return true;
+        }
+        let start = goal + 1;
+        if start >= BITS {
+            return false;
+        }
+        let (word, bit) = (start / 64, start % 64);
+        self.words[word] >> bit != 0 || self.words[word+1..].iter().any(|&w| w != 0)
+    }
+
+    // Remove every period <= goal
+    pub fn remove_up_to(&mut self, goal: usize) {
+        for n in 0..=goal.min(BITS - 1) {
+            self.words[n / 64] &= !(1 << (n % 64));
+        }
+        self.overflow.retain(|&n| n > goal);
+    }
+
+    // In increasing order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..BITS).filter(move |&n| self.words[n / 64] & (1 << (n % 64)) != 0)
+            .chain(self.overflow.iter().copied())
+    }
+}
+
+impl FromIterator<usize> for PeriodSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> PeriodSet {
+        let mut res = PeriodSet::new();
+        res.extend(iter);
+        res
+    }
+}
+
+impl Extend<usize> for PeriodSet {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+        for n in iter {
+            self.insert(n);
+        }
+    }
+}
+
+impl fmt::Debug for PeriodSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_set_operations() {
+        let mut a: PeriodSet = vec![1, 2, 3, 63, 64, 255, 256, 1000].into_iter().collect();
+        let b: PeriodSet = vec![2, 64, 65, 1000, 2000].into_iter().collect();
+        assert_eq!(a.len(), 8);
+        assert!(a.contains(255) && a.contains(256) && !a.contains(257));
+        assert!(a.any_above(999) && !a.any_above(1000));
+        assert_eq!(format!("{:?}", a), "{1, 2, 3, 63, 64, 255, 256, 1000}");
+
+        let c = a.intersection(&b);
+        a.intersect_with(&b);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![2, 64, 1000]);
+        assert_eq!(a, c);
+        assert!(a.is_subset(&b) && !b.is_subset(&a));
+
+        a.remove_up_to(64);
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![1000]);
+        a.remove_up_to(1000);
+        assert!(a.is_empty() && !a.any_above(0));
+
+        let small: PeriodSet = vec![3].into_iter().collect();
+        assert!(small.any_above(2) && !small.any_above(3));
+    }
+}
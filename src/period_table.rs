@@ -0,0 +1,118 @@
+// The possible periods of z^d + c mod p for every c, stored once per
+// conjugacy class. Conjugating by x -> zx turns z^d + c into
+// z^(d-1) x^d + c/z, so when z is a (d-1)-th root of unity mod p the
+// maps for c and zc have the same cycles and multipliers. There are
+// gcd(d-1, p-1) such roots, so only about 1/gcd(d-1, p-1) of the
+// residues need their functional graph walked (and stored)
+
+use crate::math::*;
+use crate::period_set::PeriodSet;
+use num_integer::Integer;
+use std::ops::Index;
+
+#[derive(Default)]
+pub struct PeriodTable {
+    // One entry per class, in order of their smallest c
+    classes: Vec<PeriodSet>,
+    // class_of[c] is the index in classes of the class of c
+    class_of: Vec<u32>,
+}
+
+// The table of a prime without one
+pub static EMPTY_TABLE: PeriodTable = PeriodTable { classes: Vec::new(), class_of: Vec::new() };
+
+#[allow(unused)]
+impl PeriodTable {
+    // The table for z^d + c mod p, calling periods(c) for the smallest
+    // c in each class only
+    pub fn unicritical<F>(p: usize, d: usize, periods: F) -> PeriodTable
+        where F: Fn(usize) -> PeriodSet
+    {
+        let roots = roots_of_unity(p, d - 1);
+        let m = Modulus::new(p as i64);
+        let mut class_of = vec![u32::MAX; p];
+        let mut classes = Vec::new();
+        for c in 0..p {
+            if class_of[c] != u32::MAX {
+                continue;
+            }
+            for &z in &roots {
+                class_of[m.mul(c as u64, z) as usize] = classes.len() as u32;
+            }
+            classes.push(periods(c));
+        }
+        PeriodTable { classes, class_of }
+    }
+
+    #[inline]
+    pub fn get(&self, c: usize) -> &PeriodSet {
+        &self.classes[self.class_of[c] as usize]
+    }
+
+    // The number of residues c (p, or 0 for an empty table)
+    pub fn len(&self) -> usize {
+        self.class_of.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.class_of.is_empty()
+    }
+
+    // The number of classes, so of period sets actually computed
+    pub fn class_count(&self) -> usize {
+        self.classes.len()
+    }
+}
+
+impl Index<usize> for PeriodTable {
+    type Output = PeriodSet;
+
+    fn index(&self, c: usize) -> &PeriodSet {
+        self.get(c)
+    }
+}
+
+// The n-th roots of unity mod p, starting with 1
+fn roots_of_unity(p: usize, n: usize) -> Vec<u64> {
+    let count = n.gcd(&(p - 1));
+    if count == 1 {
+        return vec![1];
+    }
+    let m = Modulus::new(p as i64);
+    let z = m.pow(primitive_root(p as i64) as u64, ((p - 1) / count) as u64);
+    (0..count).scan(1, |x, _| {
+        let cur = *x;
+        *x = m.mul(cur, z);
+        Some(cur)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ds_helper::*;
+    use crate::util::*;
+
+    #[test]
+    fn compressed_tables_match_direct() {
+        for p in (2..200).filter(|&p| prime(p)) {
+            let z4 = |c: usize| fast_possible_periods(Polynomial::new(vec![1, 0, 0, 0, c as i64], Some(p as i64)));
+            let table = PeriodTable::unicritical(p, 4, z4);
+            assert_eq!(table.len(), p);
+            // 0 on its own, the rest in classes of gcd(3, p - 1)
+            assert_eq!(table.class_count(), 1 + (p - 1) / 3.gcd(&(p - 1)));
+            for c in 0..p {
+                assert_eq!(table[c], z4(c));
+            }
+            // and c ~ -c for z^3 + c
+            let z3 = |c: usize| fast_possible_periods(Polynomial::new(vec![1, 0, 0, c as i64], Some(p as i64)));
+            let table = PeriodTable::unicritical(p, 3, z3);
+            assert_eq!(table.class_count(), 1 + (p - 1) / 2.gcd(&(p - 1)));
+            assert!((0..p).all(|c| table[c] == z3(c)));
+        }
+        let roots = roots_of_unity(7, 3);
+        assert_eq!(roots.len(), 3);
+        assert!(roots.iter().all(|&z| z * z * z % 7 == 1));
+        assert!(EMPTY_TABLE.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+// Upper bounds on how many rational periodic points a polynomial over
+// Q can have, from its cycles mod primes of good reduction. The points
+// of exact period n are roots of Phi_n, and the roots of Phi_n (in
+// C_p, even) in the residue disc of a point a mod p number at most the
+// multiplicity of a as a root of Phi_n mod p. So at each prime:
+//  - if no cycle mod p allows a period of n, there are no n-cycles;
+//  - if every cycle that does has length n and multiplier other than
+//    1, a is a simple root and each of those cycles has at most one
+//    rational n-cycle above it;
+//  - otherwise there are at most (roots of Phi_n mod p in F_p, with
+//    multiplicity) / n of them, when Phi_n is small enough to reduce.
+// The bound for n is the smallest any prime gives
+
+use crate::bifurcation::dynatomic_degree;
+use crate::cycle_structure::CycleStructure;
+use crate::fp_poly::FpPoly;
+use crate::math::*;
+use crate::period_set::PeriodSet;
+use crate::util::*;
+use std::fmt;
+
+// Largest Phi_n worth reducing mod p to count its roots
+const MAX_DYNATOMIC_DEGREE: usize = 1024;
+
+// Largest prime used for the bounds
+const MAX_BOUND_PRIME: usize = 100;
+
+pub struct PeriodicPointBound {
+    // (n, the most rational n-cycles there can be, if bounded)
+    pub cycles: Vec<(usize, Option<usize>)>,
+}
+
+#[allow(unused)]
+impl PeriodicPointBound {
+    // The most rational periodic points of all the periods in the
+    // bound there can be (None if some n-cycles aren't bounded)
+    pub fn total(&self) -> Option<usize> {
+        self.cycles.iter().map(|&(n, b)| b.map(|b| n * b)).sum()
+    }
+
+    pub fn cycles_of(&self, n: usize) -> Option<usize> {
+        self.cycles.iter().find(|&&(m, _)| m == n).and_then(|&(_, b)| b)
+    }
+}
+
+// At most how many rational n-cycles reduce into the cycles of f mod p
+// (s being its CycleStructure), or None if this prime can't tell
+pub fn cycle_count_bound(f: &Polynomial, s: &CycleStructure, n: usize) -> Option<usize> {
+    let allowing = s.cycles_allowing(n, 1);
+    if allowing.is_empty() {
+        return Some(0);
+    }
+    if allowing.iter().all(|c| c.length == n && c.multiplier != 1) {
+        return Some(allowing.len());
+    }
+    if dynatomic_degree(f.coeffs.len() - 1, n) > MAX_DYNATOMIC_DEGREE {
+        return None;
+    }
+    let phi = FpPoly::from_polynomial(f).dynatomic(n);
+    let roots: usize = phi.square_free_decomposition().iter()
+        .map(|(g, k)| g.roots_part().degree() * k)
+        .sum();
+    Some(roots / n)
+}
+
+// Bounds on the rational n-cycles of f for each n in periods, from the
+// primes up to MAX_BOUND_PRIME where f has good reduction
+pub fn periodic_point_bound(f: &PolynomialInQ, periods: &PeriodSet) -> PeriodicPointBound {
+    let mut cycles: Vec<(usize, Option<usize>)> = periods.iter().map(|n| (n, None)).collect();
+    for p in (2..=MAX_BOUND_PRIME).filter(|&p| prime(p) && f.has_good_reduction(p)) {
+        let red = f.do_reduction(p);
+        let s = CycleStructure::new(&red);
+        for (n, bound) in cycles.iter_mut() {
+            if *bound == Some(0) {
+                continue;
+            }
+            if let Some(b) = cycle_count_bound(&red, &s, *n) {
+                *bound = Some(bound.map_or(b, |a| a.min(b)));
+            }
+        }
+    }
+    PeriodicPointBound { cycles }
+}
+
+impl fmt::Display for PeriodicPointBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.cycles.iter().map(|&(n, b)| match b {
+            Some(b) => format!("{} {}-cycles", b, n),
+            None => format!("? {}-cycles", n),
+        }).collect();
+        write!(f, "at most {}", parts.join(", "))?;
+        match self.total() {
+            Some(t) => write!(f, "; at most {} periodic points", t),
+            None => write!(f, "; no bound on periodic points"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_for_quadratics() {
+        // z^2 - 29/16 has exactly one rational 3-cycle, and its fixed
+        // points and 2-cycle are in Q(sqrt 33) and Q(sqrt 17)
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(-29, 16)]);
+        let bound = periodic_point_bound(&f, &(1..=3).collect());
+        assert_eq!(bound.cycles, vec![(1, Some(0)), (2, Some(0)), (3, Some(1))]);
+        assert_eq!(bound.total(), Some(3));
+        assert_eq!(format!("{}", bound), "at most 0 1-cycles, 0 2-cycles, 1 3-cycles; at most 3 periodic points");
+
+        // z^2 - 1 has fixed points (1 +- sqrt 5)/2 and the 2-cycle 0 <-> -1
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), -Rational::one()]);
+        let bound = periodic_point_bound(&f, &(1..=2).collect());
+        assert_eq!(bound.cycles_of(1), Some(0));
+        assert_eq!(bound.cycles_of(2), Some(1));
+
+        // Mod 3, z^2 has the fixed points 0 and 1 with multipliers 0
+        // and 2, so at most two fixed points over Q
+        let red = Polynomial::new(vec![1, 0, 0], Some(3));
+        assert_eq!(cycle_count_bound(&red, &CycleStructure::new(&red), 1), Some(2));
+
+        // z^2 + 82673/4849845 has a fixed point mod every odd prime up to
+        // 100 where it has good reduction, as 1 - 4c is a square there,
+        // but mod 2 it's z^2 + 1, which swaps 0 and 1
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), Rational::new(82_673, 4_849_845)]);
+        for p in (3..=MAX_BOUND_PRIME).filter(|&p| prime(p) && f.has_good_reduction(p)) {
+            assert!(CycleStructure::new(&f.do_reduction(p)).cycles.iter().any(|c| c.length == 1));
+        }
+        assert_eq!(periodic_point_bound(&f, &(1..=1).collect()).cycles_of(1), Some(0));
+    }
+}
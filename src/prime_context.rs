@@ -0,0 +1,147 @@
+// Everything about a prime p that the searches would otherwise
+// recompute for every parameter: the images of w in F_p (a square
+// root of -3 each), a table of inverses, and the rows of the period
+// tables. Built once for every prime up to 300 and only read after,
+// so the rayon workers can share them
+
+use crate::util::*;
+use crate::math::*;
+use crate::ds_helper::*;
+use crate::period_table::PeriodTable;
+
+pub struct PrimeContext {
+    pub p: usize,
+    pub modulus: Modulus,
+    // The square roots of -3 mod p (None when there are none)
+    #[allow(unused)]
+    pub sqrt_minus_3: Option<(i64, i64)>,
+    // As qw_homomorphisms(p)
+    pub w_images: Vec<i64>,
+    // inverses[a] is a^(-1) mod p (and inverses[0] = 0)
+    pub inverses: Vec<u64>,
+    // The rows for p of the z^4 + c and z^3 + c period tables
+    // (empty above the primes they cover)
+    pub z4_table: &'static PeriodTable,
+    pub z3_table: &'static PeriodTable,
+}
+
+// Largest prime with a context
+const MAX_CONTEXT_PRIME: usize = 300;
+
+lazy_static! {
+    static ref PRIME_CONTEXTS: Vec<PrimeContext> = {
+        (2..=MAX_CONTEXT_PRIME).filter(|&p| prime(p)).map(PrimeContext::new).collect()
+    };
+}
+
+// The contexts of all primes up to 300, in increasing order
+pub fn prime_contexts() -> &'static [PrimeContext] {
+    &PRIME_CONTEXTS
+}
+
+#[allow(unused)]
+pub fn prime_context(p: usize) -> &'static PrimeContext {
+    let i = PRIME_CONTEXTS.binary_search_by_key(&p, |ctx| ctx.p).expect("no context for p");
+    &PRIME_CONTEXTS[i]
+}
+
+#[allow(unused)]
+impl PrimeContext {
+    pub fn new(p: usize) -> PrimeContext {
+        let p_i = p as i64;
+        let modulus = Modulus::new(p_i);
+        let sqrt_minus_3 = if p == 2 { None } else { cipolla(-3, p_i) };
+        let inverses: Vec<u64> = (0..p as u64).map(|a| modulus.inverse(a).unwrap_or(0)).collect();
+        // w = (-1 + sqrt(-3)) / 2, except at 3 where w = 1 is a double root
+        let w_images = match sqrt_minus_3 {
+            _ if p == 3 => vec![1],
+            Some((s, t)) if p_i % 3 == 1 => [s, t].iter()
+                .map(|&s| modulus.mul(modulus.reduce(s - 1), inverses[2]) as i64)
+                .collect(),
+            _ => Vec::new(),
+        };
+        PrimeContext {
+            p,
+            modulus,
+            sqrt_minus_3,
+            w_images,
+            inverses,
+            z4_table: z4_table_row(p),
+            z3_table: z3_table_row(p),
+        }
+    }
+
+    // a/b mod p, or None if p divides b
+    #[inline]
+    pub fn divide(&self, a: i64, b: i64) -> Option<usize> {
+        let b = self.modulus.reduce(b);
+        if b == 0 {
+            return None;
+        }
+        Some(self.modulus.mul(self.modulus.reduce(a), self.inverses[b as usize]) as usize)
+    }
+
+    pub fn reduce_rational(&self, c: Rational) -> Option<usize> {
+        self.divide(c.numer, c.denom)
+    }
+
+    // As EisensteinInteger::reductions, one for each image of w
+    pub fn reduce_eisenstein(&self, x: EisensteinInteger) -> impl Iterator<Item = u64> + '_ {
+        let (a, b) = x.coords();
+        let m = self.modulus;
+        self.w_images.iter().map(move |&w| m.add(m.reduce(a), m.mul(w as u64, m.reduce(b))))
+    }
+
+    // As QwElement::reductions
+    pub fn reduce_qw(&self, c: QwElement) -> impl Iterator<Item = Option<usize>> + '_ {
+        self.reduce_eisenstein(c.numer()).zip(self.reduce_eisenstein(c.denom())).map(move |(a, b)| {
+            if b == 0 {
+                None
+            } else {
+                Some(self.modulus.mul(a, self.inverses[b as usize]) as usize)
+            }
+        })
+    }
+
+    // As PolynomialInQw::reductions
+    pub fn reduce_qw_polynomial(&self, f: &PolynomialInQw) -> Vec<Option<Polynomial>> {
+        let coeffs: Vec<Vec<Option<usize>>> = f.coeffs.iter().map(|&c| self.reduce_qw(c).collect()).collect();
+        (0..self.w_images.len()).map(|i| {
+            coeffs.iter()
+                .map(|c| c[i].map(|r| r as i64))
+                .collect::<Option<Vec<i64>>>()
+                .filter(|v| v[0] != 0)
+                .map(|v| Polynomial::new(v, Some(self.p as i64)))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contexts_agree_with_direct_reductions() {
+        let elements = [
+            QwElement::new(EisensteinInteger::new(-8, 5), EisensteinInteger::new(6, 7)),
+            QwElement::new(EisensteinInteger::new(123, -45), EisensteinInteger::new(2, 0)),
+            QwElement::new(EisensteinInteger::new(0, 1), EisensteinInteger::new(3, 1)),
+        ];
+        for ctx in prime_contexts() {
+            let p = ctx.p as i64;
+            assert_eq!(ctx.w_images, qw_homomorphisms(p));
+            if let Some((s, t)) = ctx.sqrt_minus_3 {
+                assert_eq!((s * s + 3) % p, 0);
+                assert_eq!((t * t + 3) % p, 0);
+            }
+            for &c in &elements {
+                let direct: Vec<Option<usize>> = c.reductions(p).iter().map(|r| r.map(|r| r as usize)).collect();
+                assert_eq!(ctx.reduce_qw(c).collect::<Vec<_>>(), direct);
+            }
+            let c = Rational::new(-29, 16);
+            assert_eq!(ctx.reduce_rational(c), if p == 2 { None } else { Some(c.reduce(ctx.p)) });
+        }
+        assert_eq!(prime_context(97).z4_table.len(), 97);
+        assert_eq!(prime_context(293).z3_table.len(), 293);
+    }
+}
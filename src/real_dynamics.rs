@@ -0,0 +1,688 @@
+use std::cmp::Ordering;
+use num_rational::BigRational;
+
+use crate::util::*;
+use crate::period_set::PeriodSet;
+
+// Every rational cycle of z^d + c with c in Q is a real cycle, so
+// the real dynamics of the family rule out whole ranges of c.
+//
+// For odd d, z^d + c is increasing on R and the only real
+// periodic points are fixed points. For even d the family is
+// unimodal and monotone in c: as c decreases, real periods appear
+// in the Sharkovskii order 1, 2, 4, 8, ..., ..., 5, 3, and never
+// disappear again. So the first period above `goal` to appear is
+// the smallest power of two 2^k > goal, born when the real
+// 2^(k-1)-cycle has multiplier -1 (or, for goal = 0, when the
+// fixed points are born at the saddle node).
+
+// Keep the bisection / continuation below out of the part of the
+// cascade where f64 can't separate the bifurcations any more.
+// Larger goals fall back to the last computed threshold, which lies
+// above the true one and so never excludes an admissible c.
+const MAX_DOUBLING: u32 = 5;
+
+// Width of the rational enclosure placed around thresholds that
+// are only known numerically
+const ENCLOSURE_RADIUS: f64 = 1e-9;
+const ENCLOSURE_DENOM: i64 = 1_000_000_000_000;
+
+// A real algebraic number used as the endpoint of an interval of c.
+#[derive(Debug, Clone)]
+pub enum Threshold {
+    // The unique root in [lo, hi] of the integer polynomial
+    // poly (coefficients from the leading one down, like
+    // Polynomial), which changes sign on [lo, hi]
+    Exact { poly: Vec<i64>, lo: Rational, hi: Rational },
+    // Only known to lie in [lo, hi]
+    Enclosed { lo: Rational, hi: Rational },
+}
+
+impl Threshold {
+    // Compare c against the threshold. Returns None only if
+    // that can't be decided exactly: c lies inside the enclosure
+    // of an Enclosed threshold.
+    pub fn compare(&self, c: Rational) -> Option<Ordering> {
+        match self {
+            Threshold::Exact { poly, lo, hi } => {
+                if c < *lo {
+                    return Some(Ordering::Less);
+                }
+                if c > *hi {
+                    return Some(Ordering::Greater);
+                }
+                let at_c = sign_at(poly, c);
+                if at_c == 0 {
+                    return Some(Ordering::Equal);
+                }
+                if at_c == sign_at(poly, *lo) {
+                    Some(Ordering::Less)
+                } else {
+                    Some(Ordering::Greater)
+                }
+            },
+            Threshold::Enclosed { lo, hi } => {
+                if c < *lo {
+                    Some(Ordering::Less)
+                } else if c > *hi {
+                    Some(Ordering::Greater)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    #[allow(unused)]
+    pub fn approx(&self) -> f64 {
+        let (lo, hi) = match self {
+            Threshold::Exact { lo, hi, .. } => (lo, hi),
+            Threshold::Enclosed { lo, hi } => (lo, hi),
+        };
+        (to_f64(*lo) + to_f64(*hi)) / 2.0
+    }
+}
+
+// An interval of c, unbounded where an endpoint is None.
+// Only upper endpoints come up for the families z^d + c.
+#[derive(Debug, Clone)]
+pub struct ParameterInterval {
+    pub upper: Option<Threshold>,
+    pub upper_inclusive: bool,
+}
+
+impl ParameterInterval {
+    // Conservative membership test: if c can't be compared
+    // exactly with the endpoint, it is counted as inside
+    pub fn contains(&self, c: Rational) -> bool {
+        match &self.upper {
+            None => true,
+            Some(t) => match t.compare(c) {
+                None | Some(Ordering::Less) => true,
+                Some(Ordering::Equal) => self.upper_inclusive,
+                Some(Ordering::Greater) => false,
+            }
+        }
+    }
+}
+
+// The intervals of c for which z^d + c has real periodic
+// points of some period greater than goal
+pub fn real_cycle_parameters(d: u32, goal: usize) -> Vec<ParameterInterval> {
+    assert!(d >= 2);
+    if d % 2 == 1 {
+        return if goal == 0 {
+            vec![ParameterInterval { upper: None, upper_inclusive: false }]
+        } else {
+            vec![]
+        };
+    }
+    if goal == 0 {
+        return vec![ParameterInterval { upper: Some(saddle_node_threshold(d)), upper_inclusive: true }];
+    }
+    // Smallest k with 2^k > goal
+    let mut k = 1;
+    while (1usize << k) <= goal && k < MAX_DOUBLING {
+        k += 1;
+    }
+    vec![ParameterInterval { upper: Some(doubling_threshold(d, k)), upper_inclusive: false }]
+}
+
+// Convenience wrapper for the searches
+pub fn may_have_real_cycles(intervals: &[ParameterInterval], c: Rational) -> bool {
+    intervals.iter().any(|i| i.contains(c))
+}
+
+// Fixed points of z^d + c are the critical points of z - z^d,
+// so they exist exactly for c <= t(d-1)/d with t = d^(-1/(d-1)),
+// which is equivalent to d^d c^(d-1) <= (d-1)^(d-1)
+fn saddle_node_threshold(d: u32) -> Threshold {
+    let approx = saddle_node_approx(d);
+    match (checked_power(d, d), checked_power(d - 1, d - 1)) {
+        (Some(lead), Some(constant)) => isolate(monomial_poly(d, lead, -constant), approx),
+        _ => enclose(approx),
+    }
+}
+
+fn saddle_node_approx(d: u32) -> f64 {
+    let d = d as f64;
+    d.powf(-1.0 / (d - 1.0)) * (d - 1.0) / d
+}
+
+// The parameter where the real 2^(k-1)-cycle of z^d + c
+// (d even) has multiplier -1 and a 2^k-cycle is born
+fn doubling_threshold(d: u32, k: u32) -> Threshold {
+    if k == 1 {
+        // The fixed point -t with t = d^(-1/(d-1)) has multiplier -1
+        // at c = -t(d+1)/d, and c is below that exactly when
+        // d^d (-c)^(d-1) > (d+1)^(d-1)
+        return match (checked_power(d, d), checked_power(d + 1, d - 1)) {
+            (Some(lead), Some(constant)) => isolate(monomial_poly(d, -lead, -constant), doubling_approx(d, 1)),
+            _ => enclose(doubling_approx(d, 1)),
+        };
+    }
+    if d == 2 && k == 2 {
+        // The 2-cycle of z^2 + c has multiplier 4(c + 1)
+        return isolate(vec![4, 5], -1.25);
+    }
+    if d == 4 && k == 2 {
+        // The resultant in x of Phi_2(x, c) and 16 x^3 (x^4 + c)^3 + 1,
+        // which vanishes when a 2-cycle has multiplier -1, is the
+        // square of this (one factor for each point of the cycle)
+        let poly = vec![16_777_216, 0, 0, 53_411_840, 0, 0, 59_113_216, 0, 0, 24_137_569];
+        return isolate(poly, doubling_approx(d, k));
+    }
+    // The rest (none of them used by the searches) are only known
+    // numerically
+    enclose(doubling_approx(d, k))
+}
+
+fn enclose(c: f64) -> Threshold {
+    Threshold::Enclosed {
+        lo: floor_rational(c - ENCLOSURE_RADIUS),
+        hi: ceil_rational(c + ENCLOSURE_RADIUS),
+    }
+}
+
+// a^e, or None if it doesn't fit an i64 coefficient (from d = 16 on,
+// where the thresholds fall back to an enclosure)
+fn checked_power(a: u32, e: u32) -> Option<i64> {
+    (a as i64).checked_pow(e)
+}
+
+// Numerically locate the doubling parameter by following the
+// cascade: from the superattracting parameter of the 2^(k-1)-cycle
+// (where 0 is periodic), continue the cycle down in c until its
+// multiplier passes -1, then bisect.
+fn doubling_approx(d: u32, k: u32) -> f64 {
+    if k == 1 {
+        let df = d as f64;
+        return -df.powf(-1.0 / (df - 1.0)) * (df + 1.0) / df;
+    }
+    let n = 1usize << (k - 1);
+    let prev = doubling_approx(d, k - 1);
+    let prev_prev = if k == 2 { saddle_node_approx(d) } else { doubling_approx(d, k - 2) };
+    let step = (prev_prev - prev) / 400.0;
+
+    // The largest root of f_c^n(0) below prev
+    let crit = |c: f64| orbit(d, c, 0.0, n).0;
+    let mut hi = prev - step;
+    let sign = crit(hi) > 0.0;
+    let mut lo = hi - step;
+    while (crit(lo) > 0.0) == sign {
+        hi = lo;
+        lo -= step;
+    }
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if (crit(mid) > 0.0) == sign {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    let superattracting = (lo + hi) / 2.0;
+
+    // Follow the cycle through 0 down until the multiplier is below -1
+    let mut c_hi = superattracting;
+    let mut z_hi = 0.0;
+    let (mut c_lo, mut z_lo) = (c_hi, z_hi);
+    loop {
+        c_lo -= step;
+        z_lo = cycle_point(d, c_lo, z_lo, n);
+        if orbit(d, c_lo, z_lo, n).1 < -1.0 {
+            break;
+        }
+        c_hi = c_lo;
+        z_hi = z_lo;
+    }
+    for _ in 0..100 {
+        let mid = (c_lo + c_hi) / 2.0;
+        let z = cycle_point(d, mid, z_hi, n);
+        if orbit(d, mid, z, n).1 < -1.0 {
+            c_lo = mid;
+        } else {
+            c_hi = mid;
+            z_hi = z;
+        }
+    }
+    (c_lo + c_hi) / 2.0
+}
+
+// Returns (f^n(z), (f^n)'(z)) for f = z^d + c
+fn orbit(d: u32, c: f64, z: f64, n: usize) -> (f64, f64) {
+    let mut z = z;
+    let mut der = 1.0;
+    for _ in 0..n {
+        der *= (d as f64) * z.powi(d as i32 - 1);
+        z = z.powi(d as i32) + c;
+    }
+    (z, der)
+}
+
+// Newton's method for f^n(z) = z starting from z
+fn cycle_point(d: u32, c: f64, z: f64, n: usize) -> f64 {
+    let mut z = z;
+    for _ in 0..50 {
+        let (fz, der) = orbit(d, c, z, n);
+        let delta = (fz - z) / (der - 1.0);
+        z -= delta;
+        if delta.abs() < 1e-15 {
+            break;
+        }
+    }
+    z
+}
+
+// lead * c^(d-1) + constant, as a coefficient vector
+fn monomial_poly(d: u32, lead: i64, constant: i64) -> Vec<i64> {
+    let mut poly = vec![0; d as usize];
+    poly[0] = lead;
+    poly[d as usize - 1] = constant;
+    poly
+}
+
+// Build an Exact threshold for the simple root of poly near approx,
+// widening the enclosure until poly changes sign across it
+fn isolate(poly: Vec<i64>, approx: f64) -> Threshold {
+    let mut radius = ENCLOSURE_RADIUS;
+    loop {
+        let lo = floor_rational(approx - radius);
+        let hi = ceil_rational(approx + radius);
+        let (s_lo, s_hi) = (sign_at(&poly, lo), sign_at(&poly, hi));
+        if s_lo * s_hi <= 0 {
+            return Threshold::Exact { poly, lo, hi };
+        }
+        radius *= 2.0;
+    }
+}
+
+// The sign of poly at c, computed exactly as the sign of
+// b^n poly(a/b) in i128, or with big rationals if that overflows
+fn sign_at(poly: &[i64], c: Rational) -> i64 {
+    sign_at_i128(poly, c).unwrap_or_else(|| {
+        let c = BigRational::new(c.numer.into(), c.denom.into());
+        let zero = BigRational::from_integer(0.into());
+        let res = poly.iter().fold(zero.clone(), |res, &coeff| res * &c + BigRational::from_integer(coeff.into()));
+        match res.cmp(&zero) {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        }
+    })
+}
+
+fn sign_at_i128(poly: &[i64], c: Rational) -> Option<i64> {
+    let (a, b) = (c.numer as i128, c.denom as i128);
+    let mut res: i128 = 0;
+    let mut b_pow: i128 = 1;
+    // Horner's rule on the homogenized polynomial
+    for (i, &coeff) in poly.iter().enumerate() {
+        if i > 0 {
+            b_pow = b_pow.checked_mul(b)?;
+        }
+        res = res.checked_mul(a)?.checked_add((coeff as i128).checked_mul(b_pow)?)?;
+    }
+    Some(res.signum() as i64)
+}
+
+fn floor_rational(x: f64) -> Rational {
+    Rational::new((x * ENCLOSURE_DENOM as f64).floor() as i64, ENCLOSURE_DENOM)
+}
+
+fn ceil_rational(x: f64) -> Rational {
+    Rational::new((x * ENCLOSURE_DENOM as f64).ceil() as i64, ENCLOSURE_DENOM)
+}
+
+fn to_f64(r: Rational) -> f64 {
+    r.numer as f64 / r.denom as f64
+}
+
+// Kneading theory for z^d + c with d even and c rational.
+//
+// The map has its minimum c at the critical point 0, so every point
+// of a real cycle is >= c, and itineraries (L for x < 0, R for x > 0)
+// are ordered like the points themselves, with the comparison
+// reversed after an odd number of L's (f is decreasing on L).
+// So a periodic L/R sequence can only be the itinerary of a real
+// cycle if every shift of it is >= K, the itinerary of c (the
+// kneading sequence).
+//
+// A cycle of period n has an itinerary of some primitive period m
+// dividing n. Then f^m is monotone on the interval of points with
+// that itinerary, so n = m, or n = 2m when f^m reverses orientation
+// there (an odd number of L's), as happens after a period doubling.
+
+// How much of the critical orbit to follow
+const KNEADING_LENGTH: usize = 64;
+// Larger periods are never ruled out (the Lyndon words to check
+// grow like 2^n / n when K allows most of them)
+const MAX_KNEADING_PERIOD: usize = 24;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Symbol {
+    L,
+    R,
+}
+
+// A closed interval of reals with endpoints rounded outwards
+// after every operation, so it always contains the exact value
+#[derive(Debug, Copy, Clone)]
+struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+impl Interval {
+    fn from_rational(r: Rational) -> Interval {
+        Interval::widened(r.numer as f64 / r.denom as f64, r.numer as f64 / r.denom as f64)
+    }
+
+    // Every operation below is a few correctly rounded steps, so
+    // a relative slack of a few epsilon covers the rounding error
+    fn widened(lo: f64, hi: f64) -> Interval {
+        let slack = 4.0 * f64::EPSILON;
+        Interval {
+            lo: lo - lo.abs() * slack - f64::MIN_POSITIVE,
+            hi: hi + hi.abs() * slack + f64::MIN_POSITIVE,
+        }
+    }
+
+    fn add(self, other: Interval) -> Interval {
+        Interval::widened(self.lo + other.lo, self.hi + other.hi)
+    }
+
+    fn powi(self, d: u32) -> Interval {
+        let (a, b) = (self.lo.abs(), self.hi.abs());
+        if d % 2 == 1 {
+            // Odd powers are increasing
+            let lo = self.lo.abs().powi(d as i32).copysign(self.lo);
+            let hi = self.hi.abs().powi(d as i32).copysign(self.hi);
+            return Interval::widened_pow(lo, hi, d);
+        }
+        if self.lo <= 0.0 && self.hi >= 0.0 {
+            Interval::widened_pow(0.0, a.max(b).powi(d as i32), d)
+        } else {
+            let (small, large) = if a < b { (a, b) } else { (b, a) };
+            Interval::widened_pow(small.powi(d as i32), large.powi(d as i32), d)
+        }
+    }
+
+    // powi takes up to 2 log2(d) roundings
+    fn widened_pow(lo: f64, hi: f64, d: u32) -> Interval {
+        let slack = 2.0 * (32 - d.leading_zeros()) as f64 * f64::EPSILON;
+        Interval {
+            lo: lo - lo.abs() * slack - f64::MIN_POSITIVE,
+            hi: hi + hi.abs() * slack + f64::MIN_POSITIVE,
+        }
+    }
+
+    fn symbol(&self) -> Option<Symbol> {
+        if self.hi < 0.0 {
+            Some(Symbol::L)
+        } else if self.lo > 0.0 {
+            Some(Symbol::R)
+        } else {
+            None
+        }
+    }
+}
+
+// The certified prefix of the kneading sequence of z^d + c: the
+// itinerary of c, up to the first point where the interval
+// enclosure of the critical orbit can't tell the sign
+pub fn kneading_sequence(d: u32, c: Rational, len: usize) -> Vec<Symbol> {
+    let c = Interval::from_rational(c);
+    let mut x = c;
+    let mut res = Vec::with_capacity(len);
+    for _ in 0..len {
+        match x.symbol() {
+            Some(s) => res.push(s),
+            None => break,
+        }
+        if !x.lo.is_finite() || !x.hi.is_finite() {
+            break;
+        }
+        x = x.powi(d).add(c);
+    }
+    // Once the orbit has escaped to +infinity it stays on the R side
+    if res.len() < len && x.lo.is_infinite() && x.lo > 0.0 {
+        res.resize(len, Symbol::R);
+    }
+    res
+}
+
+// Compare the infinite sequence seq against the known prefix of
+// the kneading sequence; None if they agree on the whole prefix
+fn compare_itinerary<I: Iterator<Item=Symbol>>(seq: I, kneading: &[Symbol]) -> Option<Ordering> {
+    let mut reversed = false;
+    for (a, &k) in seq.zip(kneading.iter()) {
+        if a != k {
+            let ord = a.cmp(&k);
+            return Some(if reversed { ord.reverse() } else { ord });
+        }
+        if a == Symbol::L {
+            reversed = !reversed;
+        }
+    }
+    None
+}
+
+// Could z^d + c (d even) have a real cycle of exact period n, given
+// the known prefix of its kneading sequence? Only answers false
+// when that is certain.
+pub fn real_period_possible(kneading: &[Symbol], n: usize) -> bool {
+    if n > MAX_KNEADING_PERIOD {
+        return true;
+    }
+    admissible_itinerary(kneading, n, false)
+        || (n.is_multiple_of(2) && admissible_itinerary(kneading, n / 2, true))
+}
+
+// Is there a primitive L/R sequence of period m, with an odd number
+// of L's if odd_l is set, that isn't certainly ruled out by the
+// kneading sequence? Only the least rotation of each (a Lyndon word)
+// is tried, built a symbol at a time as in the FKM algorithm, and a
+// prefix is dropped as soon as one of its shifts is certainly < K
+fn admissible_itinerary(kneading: &[Symbol], m: usize, odd_l: bool) -> bool {
+    lyndon_search(kneading, m, odd_l, &mut Vec::with_capacity(m), 1, &[])
+}
+
+// Extend the prenecklace word (of period p) to a Lyndon word of length
+// m. states[k] is Some(reversed) while word[k..] agrees with K, and
+// None once it's certainly greater (or past the known prefix of K)
+fn lyndon_search(kneading: &[Symbol], m: usize, odd_l: bool,
+                 word: &mut Vec<Symbol>, p: usize, states: &[Option<bool>]) -> bool {
+    let t = word.len();
+    if t == m {
+        if p != m {
+            return false;
+        }
+        let ls = word.iter().filter(|&&s| s == Symbol::L).count();
+        if odd_l && ls.is_multiple_of(2) {
+            return false;
+        }
+        // The shifts that still agree may differ from K further on
+        return (0..m).all(|k| {
+            states[k].is_none() || {
+                let shifted = (0..).map(|i| word[(k + i) % m]);
+                compare_itinerary(shifted, kneading) != Some(Ordering::Less)
+            }
+        });
+    }
+    let choices = match word.get(t.wrapping_sub(p)) {
+        None => vec![(Symbol::L, 1), (Symbol::R, 1)],
+        Some(Symbol::L) => vec![(Symbol::L, p), (Symbol::R, t + 1)],
+        Some(Symbol::R) => vec![(Symbol::R, p)],
+    };
+    'choices: for (s, q) in choices {
+        let mut next = states.to_vec();
+        next.push(Some(false));
+        for (k, state) in next.iter_mut().enumerate() {
+            if let Some(reversed) = *state {
+                let i = t - k;
+                if i >= kneading.len() {
+                    *state = None;
+                } else if s == kneading[i] {
+                    *state = Some(reversed != (s == Symbol::L));
+                } else {
+                    let ord = s.cmp(&kneading[i]);
+                    let ord = if reversed { ord.reverse() } else { ord };
+                    if ord == Ordering::Less {
+                        continue 'choices;
+                    }
+                    *state = None;
+                }
+            }
+        }
+        word.push(s);
+        let found = lyndon_search(kneading, m, odd_l, word, q, &next);
+        word.pop();
+        if found {
+            return true;
+        }
+    }
+    false
+}
+
+// The members of candidates that z^d + c can have as real, and so
+// as rational, periods. Everything is kept for odd d, where the
+// real_cycle_parameters bound already says all there is to say.
+pub fn real_periods_filter(d: u32, c: Rational, candidates: &PeriodSet) -> PeriodSet {
+    if d % 2 == 1 {
+        return candidates.clone();
+    }
+    let kneading = kneading_sequence(d, c, KNEADING_LENGTH);
+    candidates.iter().filter(|&n| real_period_possible(&kneading, n)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fp_poly::FpPoly;
+    use crate::math::Modulus;
+
+    #[test]
+    fn quadratic_thresholds() {
+        // Fixed points up to 1/4, 2-cycles below -3/4, 4-cycles below -5/4
+        let fixed = real_cycle_parameters(2, 0);
+        assert!(may_have_real_cycles(&fixed, Rational::new(1, 4)));
+        assert!(!may_have_real_cycles(&fixed, Rational::new(1_000_001, 4_000_000)));
+
+        let two = real_cycle_parameters(2, 1);
+        assert!(!may_have_real_cycles(&two, Rational::new(-3, 4)));
+        assert!(may_have_real_cycles(&two, Rational::new(-3_000_000_001, 4_000_000_000)));
+
+        let four = real_cycle_parameters(2, 2);
+        assert!(!may_have_real_cycles(&four, Rational::new(-5, 4)));
+        assert!(may_have_real_cycles(&four, Rational::new(-5_000_000_001, 4_000_000_000)));
+        assert!(may_have_real_cycles(&four, Rational::new(-29, 16)));
+        assert!(!may_have_real_cycles(&four, Rational::new(-1, 1)));
+
+        // The 4-cycle doubles at c = -1.3680989...
+        let eight = real_cycle_parameters(2, 4);
+        assert!((eight[0].upper.as_ref().unwrap().approx() + 1.3680989394).abs() < 1e-8);
+    }
+
+    #[test]
+    fn quartic_thresholds() {
+        let t = real_cycle_parameters(4, 2);
+        // The 2-cycle has multiplier -1 at c = -1.1196410681...
+        let approx = t[0].upper.as_ref().unwrap().approx();
+        assert!((approx + 1.1196410681).abs() < 1e-9);
+        assert!(may_have_real_cycles(&t, Rational::new(-5649488755, 639128961)));
+        assert!(may_have_real_cycles(&t, Rational::new(-112, 100)));
+        assert!(!may_have_real_cycles(&t, Rational::new(-111, 100)));
+        // and it's exact too, deciding c a hair either side of the
+        // root -1.11964106813408674...
+        assert!(may_have_real_cycles(&t, Rational::new(-11_196_410_681_341, 10_000_000_000_000)));
+        assert!(!may_have_real_cycles(&t, Rational::new(-11_196_410_681_340, 10_000_000_000_000)));
+        assert!(matches!(t[0].upper, Some(Threshold::Exact { .. })));
+
+        // Its polynomial is the square root of the resultant: their
+        // ratio is the same at every c (checked mod p)
+        let m = Modulus::new(1_000_000_007);
+        let poly = match t[0].upper.as_ref().unwrap() {
+            Threshold::Exact { poly, .. } => FpPoly::new(poly.iter().rev().map(|&a| m.reduce(a)).collect(), m),
+            _ => unreachable!(),
+        };
+        let ratio = |c: u64| {
+            let f = FpPoly::new(vec![c, 0, 0, 0, 1], m);
+            let x3 = FpPoly::new(vec![0, 0, 0, 16], m);
+            let f3 = &(&f * &f) * &f;
+            let multiplier = &(&x3 * &f3) + &FpPoly::constant(1, m);
+            let square = m.mul(poly.eval(c), poly.eval(c));
+            m.mul(f.dynatomic(2).resultant(&multiplier), m.inverse(square).unwrap())
+        };
+        assert!((2..10).all(|c| ratio(c) == ratio(1)));
+
+        // The fixed point doubling is exact, even between these
+        // two rationals well inside its enclosure
+        let t = real_cycle_parameters(4, 1);
+        let c1 = t[0].upper.as_ref().unwrap().approx();
+        let below = Rational::new((c1 * 1e11).floor() as i64 - 1, 100_000_000_000);
+        let above = Rational::new((c1 * 1e11).ceil() as i64 + 1, 100_000_000_000);
+        assert!(may_have_real_cycles(&t, below));
+        assert!(!may_have_real_cycles(&t, above));
+    }
+
+    #[test]
+    fn kneading_periods() {
+        let all: PeriodSet = (1..=12).collect();
+        let periods = |d, c| {
+            real_periods_filter(d, c, &all).iter().collect::<Vec<usize>>()
+        };
+        // Between the 4-cycle and 8-cycle doublings of z^2 + c (the
+        // attracting 4-cycle still has the itinerary (LR) of the 2-cycle)
+        assert_eq!(periods(2, Rational::new(-13, 10)), vec![1, 2, 4]);
+        // In the period 3 window everything is present
+        assert_eq!(periods(2, Rational::new(-29, 16)), (1..=12).collect::<Vec<_>>());
+        // Just above it, 3 is the only period missing
+        assert_eq!(periods(2, Rational::new(-174, 100)), vec![1, 2, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        // The critical orbit of z^4 - 8.84 escapes
+        assert_eq!(periods(4, Rational::new(-5649488755, 639128961)).len(), 12);
+        // 0 -> -1 -> 0 can't be certified, so nothing is ruled out
+        assert_eq!(periods(2, Rational::new(-1, 1)).len(), 12);
+    }
+
+    #[test]
+    fn lyndon_search_matches_every_sequence() {
+        // Try every m-bit sequence, as admissible_itinerary did before
+        let brute_force = |kneading: &[Symbol], m: usize, odd_l: bool| {
+            (0..1u32 << m).any(|mask| {
+                let word: Vec<Symbol> = (0..m).map(|i| if mask & (1 << i) != 0 { Symbol::R } else { Symbol::L }).collect();
+                let primitive = (1..m).all(|k| !m.is_multiple_of(k) || (0..m).any(|i| word[i] != word[(i + k) % m]));
+                let ls = word.iter().filter(|&&s| s == Symbol::L).count();
+                primitive && (!odd_l || !ls.is_multiple_of(2)) && (0..m).all(|k| {
+                    compare_itinerary((0..).map(|i| word[(k + i) % m]), kneading) != Some(Ordering::Less)
+                })
+            })
+        };
+        for &(d, a, b) in &[(2, -13, 10), (2, -29, 16), (2, -174, 100), (2, -14, 10), (2, -19, 10), (4, -6, 5), (4, -13, 10)] {
+            let kneading = kneading_sequence(d, Rational::new(a, b), KNEADING_LENGTH);
+            for m in 1..=12 {
+                for &odd_l in &[false, true] {
+                    assert_eq!(admissible_itinerary(&kneading, m, odd_l), brute_force(&kneading, m, odd_l));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_degree_thresholds() {
+        // 16^16 doesn't fit an i64, 14^14 does
+        for d in [14, 16, 20] {
+            let fixed = real_cycle_parameters(d, 0);
+            let t = saddle_node_approx(d);
+            assert_eq!(matches!(fixed[0].upper, Some(Threshold::Exact { .. })), d < 16);
+            assert!(may_have_real_cycles(&fixed, Rational::new((t * 1e6) as i64 - 1, 1_000_000)));
+            assert!(!may_have_real_cycles(&fixed, Rational::new((t * 1e6) as i64 + 1, 1_000_000)));
+        }
+    }
+
+    #[test]
+    fn odd_degree_has_no_real_cycles() {
+        assert!(real_cycle_parameters(3, 1).is_empty());
+        assert!(may_have_real_cycles(&real_cycle_parameters(3, 0), Rational::new(100, 1)));
+    }
+}
@@ -0,0 +1,145 @@
+// Splitting a search into tiles of roughly equal work, which rayon
+// hands out one at a time (so an idle thread can always steal one)
+
+use rayon::prelude::*;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// The z^4 + c search aims for this many tiles per thread, so that the
+// last tiles to finish are small next to the whole run
+const Z4_TILES_PER_THREAD: i64 = 16;
+
+// Narrower tiles would cost more to hand out than to search
+const Z4_MIN_TILE_WIDTH: i64 = 1 << 12;
+
+// Side of a (num_a, num_b) tile of the z^3 + c search
+pub const Z3_TILE_WIDTH: i64 = 16;
+
+// The numerators in lo..=hi for the k-th denominator ((2k)^4 in
+// search_z4_opt)
+pub struct Z4Tile {
+    pub k: i64,
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl fmt::Display for Z4Tile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "denominator #{}, a in [{}, {}]", self.k, self.lo, self.hi)
+    }
+}
+
+// The numerators a + bw with a and b in the given ranges (inclusive)
+pub struct Z3Tile {
+    pub num_a: (i64, i64),
+    pub num_b: (i64, i64),
+}
+
+impl fmt::Display for Z3Tile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a in [{}, {}], b in [{}, {}]", self.num_a.0, self.num_a.1, self.num_b.0, self.num_b.1)
+    }
+}
+
+// Cut lo..=hi into consecutive ranges of at most width integers
+pub fn split_range(lo: i64, hi: i64, width: i64) -> Vec<(i64, i64)> {
+    (0..)
+        .map(|i| lo + i * width)
+        .take_while(|&start| start <= hi)
+        .map(|start| (start, (start + width - 1).min(hi)))
+        .collect()
+}
+
+// Numerators per tile for bmax denominators of 2 height_max + 1
+// numerators each, shared by threads threads: wide enough for
+// Z4_TILES_PER_THREAD tiles each, but at most one denominator's worth
+pub fn z4_tile_width(bmax: i64, height_max: i64, threads: usize) -> i64 {
+    let numerators = 2 * height_max + 1;
+    let tiles = threads as i64 * Z4_TILES_PER_THREAD;
+    ((bmax * numerators + tiles - 1) / tiles).clamp(Z4_MIN_TILE_WIDTH.min(numerators), numerators)
+}
+
+pub fn z4_tiles(bmax: i64, height_max: i64) -> Vec<Z4Tile> {
+    let width = z4_tile_width(bmax, height_max, rayon::current_num_threads());
+    let ranges = split_range(-height_max, height_max, width);
+    (1..=bmax)
+        .flat_map(|k| ranges.iter().map(move |&(lo, hi)| Z4Tile { k, lo, hi }))
+        .collect()
+}
+
+pub fn z3_tiles(height_max: i64) -> Vec<Z3Tile> {
+    let ranges = split_range(-height_max, height_max, Z3_TILE_WIDTH);
+    ranges.iter()
+        .flat_map(|&num_a| ranges.iter().map(move |&num_b| Z3Tile { num_a, num_b }))
+        .collect()
+}
+
+// Use n worker threads (0 leaves rayon's default, one per core).
+// Must be called before anything else runs on the pool
+pub fn set_threads(n: usize) {
+    if n > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build_global()
+            .unwrap_or_else(|e| panic!("Couldn't start {} threads: {}", n, e));
+    }
+}
+
+// Run work on every tile, one rayon task per tile, printing a
+// summary of the timings (and which tile took longest) at the end
+pub fn run_tiles<T, F>(tiles: Vec<T>, work: F)
+    where T: Send + fmt::Display, F: Fn(&T) + Sync + Send
+{
+    let start = Instant::now();
+    let count = tiles.len();
+    let times: Vec<(Duration, T)> = tiles.into_par_iter().with_max_len(1).map(|tile| {
+        let tile_start = Instant::now();
+        work(&tile);
+        (tile_start.elapsed(), tile)
+    }).collect();
+    let total: Duration = times.iter().map(|(t, _)| *t).sum();
+    let longest = match times.iter().max_by_key(|(t, _)| *t) {
+        Some((t, tile)) => format!("{:.3}s, {}", t.as_secs_f64(), tile),
+        None => "none".to_string(),
+    };
+    println!("Ran {} tiles on {} threads in {:.3}s (longest tile {}; {:.3}s of work in all)",
+             count, rayon::current_num_threads(), start.elapsed().as_secs_f64(),
+             longest, total.as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    #[test]
+    fn tiles_cover_the_parameter_space() {
+        assert_eq!(split_range(-5, 5, 4), vec![(-5, -2), (-1, 2), (3, 5)]);
+        assert_eq!(split_range(0, 0, 4), vec![(0, 0)]);
+        assert!(split_range(1, 0, 4).is_empty());
+
+        // 31 denominators of 2 * 10^6 + 1 numerators on 8 threads: 128
+        // tiles of 484,376, so 5 per denominator
+        let width = z4_tile_width(31, 1_000_000, 8);
+        assert_eq!(width, 484_376);
+        assert_eq!(split_range(-1_000_000, 1_000_000, width).len(), 5);
+        // Never more than a denominator, nor less than the minimum
+        assert_eq!(z4_tile_width(100, 10_000, 1), 20_001);
+        assert_eq!(z4_tile_width(2, 100_000, 64), Z4_MIN_TILE_WIDTH);
+        assert_eq!(z4_tile_width(1, 10, 64), 21);
+
+        let tiles = z4_tiles(3, 100_000);
+        assert_eq!(tiles.iter().map(|t| t.hi - t.lo + 1).sum::<i64>(), 3 * 200_001);
+
+        let sum = AtomicI64::new(0);
+        run_tiles(z3_tiles(20), |t| {
+            for a in t.num_a.0..=t.num_a.1 {
+                for b in t.num_b.0..=t.num_b.1 {
+                    sum.fetch_add(a * a + b * b, Ordering::Relaxed);
+                }
+            }
+        });
+        // 2 * 41 * (the sum of a^2 for a in -20..=20)
+        assert_eq!(sum.load(Ordering::Relaxed), 2 * 41 * 5740);
+    }
+}
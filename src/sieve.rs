@@ -0,0 +1,131 @@
+// Sieving numerators for a fixed denominator. Whether
+// z4c_possible_periods_search rules out c = a/b at a prime p only
+// depends on a b^(-1) mod p, so for each b we can find the residues
+// of a that survive each of several primes, combine them with the
+// CRT into residues mod their product, and only ever look at those
+
+use crate::ds_helper::*;
+use crate::math::*;
+use crate::period_set::PeriodSet;
+
+// Largest modulus for a wheel; its residues are kept in memory
+pub const MAX_WHEEL: i64 = 1 << 20;
+
+pub struct ResidueWheel {
+    pub modulus: i64,
+    // Sorted, in 0..modulus
+    pub residues: Vec<i64>,
+}
+
+#[allow(unused)]
+impl ResidueWheel {
+    // The wheel for z^4 + a/b with b a fourth power and a periods goal,
+    // with modulus at most limit. Primes dividing b only say that a
+    // is a unit mod p (so a/b is in lowest terms); the others say
+    // that some period > goal is still possible mod p, and the most
+    // selective of those are used first
+    pub fn z4c(b: i64, goal: usize, limit: i64) -> ResidueWheel {
+        ResidueWheel::z4c_keeping(b, limit, |periods| periods.any_above(goal))
+    }
+
+    // As z4c, keeping the residues whose row of the table passes keep
+    pub fn z4c_keeping<F>(b: i64, limit: i64, keep: F) -> ResidueWheel
+        where F: Fn(&PeriodSet) -> bool
+    {
+        let mut sieves: Vec<(i64, Vec<i64>)> = (2..=MAX_TABLE_PRIME)
+            .filter(|&p| prime(p))
+            .map(|p| (p as i64, z4c_numerators_keeping(p, b, &keep)))
+            .collect();
+        sieves.sort_by(|(p, s), (q, t)| (s.len() as i64 * q).cmp(&(t.len() as i64 * p)));
+
+        let mut wheel = ResidueWheel { modulus: 1, residues: vec![0] };
+        for (p, allowed) in sieves {
+            if wheel.modulus * p > limit {
+                continue;
+            }
+            wheel = wheel.combine(p, &allowed);
+        }
+        wheel.residues.sort_unstable();
+        wheel
+    }
+
+    // The residues mod modulus * p which are in self and reduce to
+    // something in allowed mod p (by the CRT, as p is a new prime)
+    fn combine(&self, p: i64, allowed: &[i64]) -> ResidueWheel {
+        let inv = mod_inverse(self.modulus % p, p);
+        let mut residues = Vec::with_capacity(self.residues.len() * allowed.len());
+        for &r in &self.residues {
+            for &s in allowed {
+                let t = ((s - r) % p * inv).rem_euclid(p);
+                residues.push(r + self.modulus * t);
+            }
+        }
+        ResidueWheel { modulus: self.modulus * p, residues }
+    }
+
+    // The integers in lo..=hi in the wheel, in increasing order
+    // (only the residues in range are visited, so a range much
+    // shorter than the modulus is cheap)
+    pub fn numerators(&self, lo: i64, hi: i64) -> impl Iterator<Item = i64> + '_ {
+        let first = lo.div_euclid(self.modulus) * self.modulus;
+        (0..)
+            .map(move |k| first + k * self.modulus)
+            .take_while(move |&base| base <= hi)
+            .flat_map(move |base| {
+                let start = self.residues.partition_point(|&r| base + r < lo);
+                let end = self.residues.partition_point(|&r| base + r <= hi);
+                self.residues[start..end].iter().map(move |&r| base + r)
+            })
+    }
+}
+
+// The residues of a mod p for which the row of z4c_possible_periods_search
+// at p for a/b passes keep (or a/b is in lowest terms, if p | b)
+pub fn z4c_numerators_keeping<F>(p: usize, b: i64, keep: F) -> Vec<i64>
+    where F: Fn(&PeriodSet) -> bool
+{
+    let p_i = p as i64;
+    if b % p_i == 0 {
+        return (1..p_i).collect();
+    }
+    (0..p_i)
+        .filter(|&a| {
+            let c = (a * mod_inverse(b.rem_euclid(p_i), p_i)).rem_euclid(p_i) as usize;
+            keep(z4_table_possible_periods(p, c))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::*;
+    use crate::prime_context::prime_contexts;
+    use num_integer::Integer;
+
+    #[test]
+    fn wheel_matches_direct_search() {
+        for &k in &[1i64, 3, 5] {
+            let b = (2 * k).pow(4);
+            let wheel = ResidueWheel::z4c(b, 2, 30_030);
+            assert!(wheel.modulus <= 30_030);
+            let sieved: Vec<i64> = wheel.numerators(-40_000, 40_000).collect();
+            // Nothing that could survive is sieved out, in order
+            let direct: Vec<i64> = (-40_000..=40_000i64)
+                .filter(|a| a.gcd(&b) == 1)
+                .filter(|&a| z4c_possible_periods_search(Rational::new(a, b), 2, prime_contexts()).is_some())
+                .collect();
+            let mut it = sieved.iter();
+            for a in &direct {
+                assert!(it.any(|x| x == a));
+            }
+            assert!(sieved.windows(2).all(|w| w[0] < w[1]));
+            // Pieces of the range give the same numerators
+            let pieces: Vec<i64> = (-40_000..=40_000i64).step_by(1000)
+                .flat_map(|lo| wheel.numerators(lo, (lo + 999).min(40_000)))
+                .collect();
+            assert_eq!(pieces, sieved);
+            assert!(sieved.len() < 80_001 / 2);
+        }
+    }
+}
@@ -1,48 +1,43 @@
 use std::fmt;
 
 use crate::math::*;
+use crate::fp_poly::FpPoly;
 
 use num_rational::Rational64;
 use num_complex::Complex32;
 
 use std::f32::consts::PI;
+use std::ops::{Add, Sub, Mul, Neg, Div, Rem};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use num_integer::Integer;
 
 #[derive(Debug)]
 pub struct Polynomial {
     pub coeffs: Vec<i64>,
     pub p_mod: Option<i64>,
+    // With p_mod, the modulus and the coefficients reduced by it
+    modulus: Option<Modulus>,
+    residues: Vec<u64>,
 }
 
 impl Polynomial {
     pub fn new(v: Vec<i64>, p_mod: Option<i64>) -> Polynomial {
-        Polynomial { coeffs: v, p_mod: p_mod }
+        let modulus = p_mod.map(Modulus::new);
+        let residues = match modulus {
+            Some(m) => v.iter().map(|&c| m.reduce(c)).collect(),
+            None => Vec::new(),
+        };
+        Polynomial { coeffs: v, p_mod, modulus, residues }
     }
 
     pub fn eval(&self, x: i64) -> i64 {
-        let e = self.coeffs.len();
-        let mut res = 0;
-        for indx in 0..e-1 {
-            res += self.coeffs[indx];
-            if let Some(p) = self.p_mod {
-                let r = res.checked_mul(x);
-                match r {
-                    None => {
-                        res %= p;
-                        res *= x;
-                    },
-                    Some(r) => {
-                        res = r;
-                    }
-                }
-            } else {
-                res *= x;
-            }
-        }
-        res += self.coeffs[e-1];
-        if let Some(p) = self.p_mod {
-            res.rem_euclid(p)
+        if let Some(m) = self.modulus {
+            let x = m.int(x);
+            self.residues.iter().fold(m.int(0), |res, &c| res * x + m.residue(c)).value()
         } else {
-            res
+            self.coeffs.iter().fold(0, |res, &c| res * x + c)
         }
     }
 
@@ -65,23 +60,27 @@ impl Polynomial {
         res
     }
 
+    // Over F_p, as FpPoly::resultant
+    pub fn resultant(&self, other: &Polynomial) -> i64 {
+        FpPoly::from_polynomial(self).resultant(&FpPoly::from_polynomial(other)) as i64
+    }
+
+    pub fn discriminant(&self) -> i64 {
+        FpPoly::from_polynomial(self).discriminant() as i64
+    }
+
+    // The (degree, multiplicity) of the irreducible factors over F_p
+    pub fn factor_degrees(&self) -> Vec<(usize, usize)> {
+        FpPoly::from_polynomial(self).factor_degrees()
+    }
+
     pub fn multiplier(&self, period: usize, x: i64) -> i64 {
         let orbit = self.n_orbit(x, period);
         let s_der = self.derivative();
-        if let Some(p_mod) = self.p_mod {
+        if let Some(m) = self.modulus {
             orbit.into_iter().map(|a| {
-                s_der.eval(a)
-            }).fold(1i64, |prod, i| {
-                let r = prod.checked_mul(i);
-                match r {
-                    None => {
-                        (prod % p_mod) * i
-                    },
-                    Some(r) => {
-                        r
-                    }
-                }
-            }).rem_euclid(p_mod)
+                m.int(s_der.eval(a))
+            }).fold(m.int(1), |prod, i| prod * i).value()
         } else {
             orbit.into_iter().map(|a| {
                 s_der.eval(a)
@@ -113,7 +112,9 @@ impl fmt::Display for Polynomial {
     }
 }
 
-#[derive(PartialEq, Debug, Copy, Clone)]
+// Always stored in lowest terms with a positive denominator,
+// so the derived equality and hash agree with equality in Q
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub struct Rational {
     pub numer: i64,
     pub denom: i64,
@@ -121,7 +122,9 @@ pub struct Rational {
 
 impl Rational {
     pub fn new(a: i64, b: i64) -> Rational {
-        Rational {numer: a, denom: b}
+        assert!(b != 0, "Rational with zero denominator");
+        let g = a.gcd(&b) * b.signum();
+        Rational {numer: a / g, denom: b / g}
     }
 
     pub fn zero() -> Rational {
@@ -132,8 +135,130 @@ impl Rational {
         Rational {numer: 1, denom: 1}
     }
 
+    pub fn from_integer(a: i64) -> Rational {
+        Rational {numer: a, denom: 1}
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer == 0
+    }
+
+    pub fn recip(&self) -> Rational {
+        Rational::new(self.denom, self.numer)
+    }
+
+    // The p-adic valuation, or None for zero
+    pub fn valuation(&self, p: usize) -> Option<i32> {
+        if self.numer == 0 {
+            return None;
+        }
+        Some(valuation(self.numer, p as i64) as i32 - valuation(self.denom, p as i64) as i32)
+    }
+
+    pub fn is_p_integral(&self, p: usize) -> bool {
+        self.denom % (p as i64) != 0
+    }
+
+    // p^k for any integer k
+    pub fn p_power(p: usize, k: i32) -> Rational {
+        let pk = (p as i64).pow(k.unsigned_abs());
+        if k >= 0 {
+            Rational::from_integer(pk)
+        } else {
+            Rational::new(1, pk)
+        }
+    }
+
+    // The image in Z/p, for p not dividing the denominator
     pub fn reduce(&self, p: usize) -> usize {
-        ((self.numer % (p as i64)) * mod_inverse(self.denom % (p as i64), p as i64)).rem_euclid(p as i64) as usize
+        let m = Modulus::new(p as i64);
+        let inv = m.int(self.denom).inverse().expect("denominator not invertible");
+        (m.int(self.numer) * inv).value() as usize
+    }
+}
+
+// "a/b" or "a"
+impl FromStr for Rational {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rational, String> {
+        let parse = |t: &str| t.trim().parse::<i64>().map_err(|e| format!("{}: {}", t.trim(), e));
+        match s.split_once('/') {
+            Some((a, b)) => {
+                let b = parse(b)?;
+                if b == 0 {
+                    return Err(format!("{}: zero denominator", s));
+                }
+                Ok(Rational::new(parse(a)?, b))
+            }
+            None => Ok(Rational::from_integer(parse(s)?)),
+        }
+    }
+}
+
+impl Add for Rational {
+    type Output = Rational;
+
+    fn add(self, other: Rational) -> Rational {
+        Rational::new(self.numer * other.denom + other.numer * self.denom, self.denom * other.denom)
+    }
+}
+
+impl Sub for Rational {
+    type Output = Rational;
+
+    fn sub(self, other: Rational) -> Rational {
+        Rational::new(self.numer * other.denom - other.numer * self.denom, self.denom * other.denom)
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, other: Rational) -> Rational {
+        Rational::new(self.numer * other.numer, self.denom * other.denom)
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+
+    fn div(self, other: Rational) -> Rational {
+        Rational::new(self.numer * other.denom, self.denom * other.numer)
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {numer: -self.numer, denom: self.denom}
+    }
+}
+
+// Exact comparison: denominators are positive, so compare
+// the cross products (in i128 so they can't overflow)
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        (self.numer as i128 * other.denom as i128).cmp(&(other.numer as i128 * self.denom as i128))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<Rational64> for Rational {
+    fn from(r: Rational64) -> Rational {
+        Rational::new(*r.numer(), *r.denom())
+    }
+}
+
+impl From<Rational> for Rational64 {
+    fn from(r: Rational) -> Rational64 {
+        Rational64::new_raw(r.numer, r.denom)
     }
 }
 
@@ -147,33 +272,198 @@ impl fmt::Display for Rational {
     }
 }
 
+#[derive(Clone)]
 pub struct PolynomialInQ {
     pub coeffs: Vec<Rational>,
 }
 
 impl PolynomialInQ {
     pub fn from(coeffs: Vec<Rational>) -> PolynomialInQ {
-        PolynomialInQ {coeffs: coeffs}
+        PolynomialInQ {coeffs}
     }
 
+    #[allow(dead_code)]
+    pub fn eval(&self, x: Rational) -> Rational {
+        self.coeffs.iter().fold(Rational::zero(), |acc, &c| acc * x + c)
+    }
+
+    pub fn degree(&self) -> usize {
+        self.coeffs.len() - 1
+    }
+
+    // A polynomial, as a map on P^1, has good reduction at p
+    // exactly when its coefficients are p-integral and its
+    // leading coefficient is a p-unit (otherwise the reduction
+    // drops degree, or the resultant of the homogenized model
+    // is divisible by p)
     pub fn has_good_reduction(&self, p: usize) -> bool {
         for c in &self.coeffs {
-            if c.denom % (p as i64) == 0 {
+            if !c.is_p_integral(p) {
                 return false;
             }
         }
-        return true;
+        self.coeffs[0].numer % (p as i64) != 0
+    }
+
+    // The conjugate L^-1 o f o L for L(z) = alpha z + beta
+    pub fn conjugate_affine(&self, alpha: Rational, beta: Rational) -> PolynomialInQ {
+        // Horner's rule, with polynomials in z in place of numbers
+        let mut res = vec![Rational::zero()];
+        for &c in &self.coeffs {
+            let mut next = vec![Rational::zero(); res.len() + 1];
+            for (i, &r) in res.iter().enumerate() {
+                next[i] = next[i] + r * alpha;
+                next[i + 1] = next[i + 1] + r * beta;
+            }
+            let last = next.len() - 1;
+            next[last] = next[last] + c;
+            res = next;
+        }
+        // res started with a 0 leading coefficient
+        res.remove(0);
+        let last = res.len() - 1;
+        res[last] = res[last] - beta;
+        PolynomialInQ::from(res.into_iter().map(|c| c / alpha).collect())
+    }
+
+    // Look for an affine conjugate of self over Q with good
+    // reduction at p. Returns it along with the alpha and beta of
+    // conjugate_affine, or None if there is none (or if finding
+    // one would mean trying more than MAX_MODEL_SHIFTS shifts).
+    //
+    // For g = L^-1 o f o L to have a p-unit leading coefficient
+    // v(alpha) is fixed, and the other units of alpha don't matter.
+    // Changing beta by alpha * (p-integral) conjugates g by an
+    // integral translation, so only beta mod alpha Z_p matters.
+    // When p doesn't divide the degree, the centers of f and g
+    // correspond, so beta can be the center of f. Otherwise, beta
+    // is a fixed point of f minus alpha times one of g, which is
+    // p-integral, so v(beta) is bounded by the Newton polygon of
+    // f(z) - z and there are finitely many classes to try.
+    pub fn good_reduction_model(&self, p: usize) -> Option<(PolynomialInQ, Rational, Rational)> {
+        const MAX_MODEL_SHIFTS: i64 = 4096;
+        let d = self.degree();
+        if d < 2 {
+            return None;
+        }
+        let lead = self.coeffs[0].valuation(p)?;
+        if lead % (d as i32 - 1) != 0 {
+            return None;
+        }
+        let k = -lead / (d as i32 - 1);
+        let alpha = Rational::p_power(p, k);
+
+        if !d.is_multiple_of(p) {
+            let beta = -self.coeffs[1] / (self.coeffs[0] * Rational::from_integer(d as i64));
+            let g = self.conjugate_affine(alpha, beta);
+            return if g.has_good_reduction(p) { Some((g, alpha, beta)) } else { None };
+        }
+
+        // Roots of f(z) - z have valuation at least
+        // min (v(a_i) - v(a_d)) / (d - i)
+        let mut fixed = self.coeffs.clone();
+        fixed[d - 1] = fixed[d - 1] - Rational::one();
+        let mut min_root = 0;
+        for (i, c) in fixed.iter().enumerate().skip(1) {
+            if let Some(v) = c.valuation(p) {
+                let slope = (v - lead) as f64 / i as f64;
+                min_root = min_root.min(slope.floor() as i32);
+            }
+        }
+        // beta ranges over p^-j Z_p mod p^k Z_p
+        let j = (-min_root).max(-k);
+        if j + k > 0 && (p as f64).powi(j + k) > MAX_MODEL_SHIFTS as f64 {
+            return None;
+        }
+        let classes = (p as i64).pow((j + k).max(0) as u32);
+        let scale = Rational::p_power(p, -j);
+        for b in 0..classes {
+            let beta = Rational::from_integer(b) * scale;
+            let g = self.conjugate_affine(alpha, beta);
+            if g.has_good_reduction(p) {
+                return Some((g, alpha, beta));
+            }
+        }
+        None
+    }
+
+    #[allow(dead_code)]
+    pub fn derivative(&self) -> PolynomialInQ {
+        let d = self.degree();
+        PolynomialInQ::from(self.coeffs[..d].iter().enumerate()
+            .map(|(i, &c)| c * Rational::from_integer((d - i) as i64))
+            .collect())
+    }
+
+    // The resultant, by the Euclidean algorithm over Q (so only for
+    // small polynomials: the arithmetic is in i64 throughout)
+    #[allow(dead_code)]
+    pub fn resultant(&self, other: &PolynomialInQ) -> Rational {
+        let mut a = trim_q(&self.coeffs);
+        let mut b = trim_q(&other.coeffs);
+        if a.is_empty() || b.is_empty() {
+            return Rational::zero();
+        }
+        let mut res = Rational::one();
+        // res(a, b) = (-1)^(deg a deg b) lc(b)^(deg a - deg r) res(b, r)
+        // for r = a mod b
+        while b.len() > 1 {
+            let (m, n) = (a.len() - 1, b.len() - 1);
+            let r = rem_q(&a, &b);
+            if r.is_empty() {
+                return Rational::zero();
+            }
+            if m * n % 2 == 1 {
+                res = -res;
+            }
+            for _ in 0..m - (r.len() - 1) {
+                res = res * b[0];
+            }
+            a = b;
+            b = r;
+        }
+        for _ in 0..a.len() - 1 {
+            res = res * b[0];
+        }
+        res
+    }
+
+    // (-1)^(d(d-1)/2) res(f, f') / lc(f)
+    #[allow(dead_code)]
+    pub fn discriminant(&self) -> Rational {
+        let d = self.degree();
+        let res = self.resultant(&self.derivative()) / self.coeffs[0];
+        if (d * d.saturating_sub(1) / 2) % 2 == 1 { -res } else { res }
     }
 
     pub fn do_reduction(&self, p: usize) -> Polynomial {
-        let coeffs = self.coeffs.iter().map(|c| {
-            (c.numer % (p as i64)) * mod_inverse(c.denom % (p as i64), p as i64) % (p as i64)
-        }).collect();
+        let coeffs = self.coeffs.iter().map(|c| c.reduce(p) as i64).collect();
 
         Polynomial::new(coeffs, Some(p as i64))
     }
 }
 
+// The coefficients without leading zeros
+#[allow(dead_code)]
+fn trim_q(coeffs: &[Rational]) -> Vec<Rational> {
+    coeffs.iter().copied().skip_while(|c| c.is_zero()).collect()
+}
+
+// a mod b, both highest degree first with b[0] != 0, trimmed
+#[allow(dead_code)]
+fn rem_q(a: &[Rational], b: &[Rational]) -> Vec<Rational> {
+    let mut r = a.to_vec();
+    while r.len() >= b.len() {
+        let q = r[0] / b[0];
+        for (x, &y) in r.iter_mut().zip(b.iter()) {
+            *x = *x - q * y;
+        }
+        r.remove(0);
+        r = trim_q(&r);
+    }
+    r
+}
+
 impl fmt::Display for PolynomialInQ {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut exp = self.coeffs.len() - 1;
@@ -197,7 +487,7 @@ impl fmt::Display for PolynomialInQ {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct EisensteinInteger {
     a: i64,
     b: i64
@@ -208,14 +498,19 @@ impl EisensteinInteger {
         EisensteinInteger {a, b}
     }
 
-    // Evaluate the natural homomorphisms from Z[w]
-    // to F_p sending 1 to 1 and w to sqrt(-3) in F_p
-    pub fn reductions(&self, p: i64) -> (i64, i64) {
-        debug_assert!(p > 2);
-        let d2 = mod_inverse(2, p);
-        let (s1, s2) = cipolla(p - 3, p).unwrap();
-        let (w1, w2) = ((-1 + s1)*d2, (-1 + s2)*d2);
-        ((self.a + w1 * self.b).rem_euclid(p), (self.a + w2 * self.b).rem_euclid(p))
+    // Evaluate the natural homomorphisms from Z[w] to F_p
+    // sending 1 to 1 and w to a cube root of unity in F_p
+    // (one for each of qw_homomorphisms(p))
+    pub fn reductions(&self, p: i64) -> Vec<i64> {
+        let m = Modulus::new(p);
+        qw_homomorphisms(p).iter().map(|&w| {
+            (m.int(self.a) + m.int(w) * m.int(self.b)).value()
+        }).collect()
+    }
+
+    // (a, b) for a + bw
+    pub fn coords(&self) -> (i64, i64) {
+        (self.a, self.b)
     }
 
     pub fn one() -> EisensteinInteger {
@@ -226,16 +521,9 @@ impl EisensteinInteger {
         EisensteinInteger {a:0, b:0}
     }
 
+    // The six units are exactly the elements of norm 1
     pub fn is_unit(&self) -> bool {
-        if self.a.abs() == 1 && self.b == 0 {
-            true
-        } else if self.b.abs() == 1 && self.a == 0 {
-            true
-        } else if self.a == -1 && self.b == -1 {
-            true
-        } else {
-            false
-        }
+        self.norm_sq() == 1
     }
 
     pub fn is_zero(&self) -> bool {
@@ -267,23 +555,133 @@ impl EisensteinInteger {
         let a = Rational64::new(num.a, normsq);
         let b = Rational64::new(num.b, normsq);
         // Round a and b to the nearest integer
+        // (the error x + yw has |x|, |y| <= 1/2, so its
+        // norm is at most 3/4 and this is a Euclidean division)
         let a = a.round().to_integer();
         let b = b.round().to_integer();
         EisensteinInteger { a, b }
     }
 
-    pub fn gcd(&self, other: &EisensteinInteger) -> EisensteinInteger {
-        if self.norm_sq() < other.norm_sq() {
-            return other.gcd(self);
-        }
-        assert!(!other.is_zero());
-        let q = self.division(other);
-        let remainder = self.difference(&other.product(q));
-        if remainder.is_zero() {
-            *other
+    pub fn divides(&self, other: &EisensteinInteger) -> bool {
+        if self.is_zero() {
+            other.is_zero()
+        } else {
+            (*other % *self).is_zero()
+        }
+    }
+
+    // The exponent of the prime pi in self != 0
+    pub fn valuation(&self, pi: &EisensteinInteger) -> u32 {
+        debug_assert!(!self.is_zero());
+        let mut rest = *self;
+        let mut e = 0;
+        while pi.divides(&rest) {
+            rest = rest / *pi;
+            e += 1;
+        }
+        e
+    }
+
+    // The normalized prime generating the kernel of the homomorphism
+    // Z[w] -> F_p sending w to w_image (as in qw_homomorphisms)
+    pub fn kernel_prime(p: i64, w_image: i64) -> EisensteinInteger {
+        if p == 3 {
+            EisensteinInteger::new(1, -1).normalize().0
         } else {
-            other.gcd(&remainder)
+            EisensteinInteger::new(-w_image, 1).gcd(&EisensteinInteger::new(p, 0))
+        }
+    }
+
+    // The associate of self lying in the sector 0 <= arg < pi/3
+    // (that is, 0 <= b < a), along with the unit u such that
+    // self * u is that associate. Zero is returned unchanged.
+    pub fn normalize(&self) -> (EisensteinInteger, EisensteinInteger) {
+        // 1 + w = -w^2 is rotation by pi/3
+        let rot = EisensteinInteger::new(1, 1);
+        let mut assoc = *self;
+        let mut unit = EisensteinInteger::one();
+        if self.is_zero() {
+            return (assoc, unit);
+        }
+        while !(0 <= assoc.b && assoc.b < assoc.a) {
+            assoc = assoc * rot;
+            unit = unit * rot;
+        }
+        (assoc, unit)
+    }
+
+    pub fn gcd(&self, other: &EisensteinInteger) -> EisensteinInteger {
+        self.extended_gcd(other).0
+    }
+
+    // Returns (g, x, y) with x * self + y * other = g, where
+    // g is the normalized gcd of self and other
+    pub fn extended_gcd(&self, other: &EisensteinInteger) -> (EisensteinInteger, EisensteinInteger, EisensteinInteger) {
+        let (mut r0, mut r1) = (*self, *other);
+        let (mut x0, mut x1) = (EisensteinInteger::one(), EisensteinInteger::zero());
+        let (mut y0, mut y1) = (EisensteinInteger::zero(), EisensteinInteger::one());
+        while !r1.is_zero() {
+            let q = r0 / r1;
+            let r2 = r0 - q * r1;
+            r0 = r1;
+            r1 = r2;
+            let x2 = x0 - q * x1;
+            x0 = x1;
+            x1 = x2;
+            let y2 = y0 - q * y1;
+            y0 = y1;
+            y1 = y2;
+        }
+        let (g, u) = r0.normalize();
+        (g, x0 * u, y0 * u)
+    }
+
+    // Inverse of self in Z[w]/(modulus), reduced mod modulus,
+    // or None if self is not invertible there
+    #[allow(dead_code)]
+    pub fn mod_inverse(&self, modulus: &EisensteinInteger) -> Option<EisensteinInteger> {
+        let (g, x, _) = self.extended_gcd(modulus);
+        if g != EisensteinInteger::one() {
+            return None;
+        }
+        Some(x % *modulus)
+    }
+
+    // Factor self into normalized Eisenstein primes, returning
+    // a unit u and (prime, exponent) pairs whose product,
+    // multiplied by u, is self
+    #[allow(dead_code)]
+    pub fn factor(&self) -> (EisensteinInteger, Vec<(EisensteinInteger, u32)>) {
+        assert!(!self.is_zero());
+        let mut rest = *self;
+        let mut res = Vec::new();
+        for (p, _) in factorize(self.norm_sq()) {
+            let primes = if p == 3 {
+                // 3 = -w^2 (1 - w)^2 is ramified
+                vec![EisensteinInteger::new(1, -1).normalize().0]
+            } else if p % 3 == 2 {
+                // p is inert
+                vec![EisensteinInteger::new(p, 0)]
+            } else {
+                // p splits as pi * conj(pi) where pi generates
+                // the kernel of w -> w1 mod p
+                let (w1, _) = cube_roots_of_unity(p).unwrap();
+                let pi = EisensteinInteger::new(-w1, 1).gcd(&EisensteinInteger::new(p, 0));
+                vec![pi, pi.conjugate().normalize().0]
+            };
+            for pi in primes {
+                let mut e = 0;
+                while pi.divides(&rest) {
+                    rest = rest / pi;
+                    e += 1;
+                }
+                if e > 0 {
+                    res.push((pi, e));
+                }
+            }
         }
+        debug_assert!(rest.is_unit());
+        (rest, res)
     }
 
     pub fn approx_coords(&self) -> Complex32 {
@@ -304,6 +702,56 @@ impl EisensteinInteger {
     }
 }
 
+impl Add for EisensteinInteger {
+    type Output = EisensteinInteger;
+
+    fn add(self, other: EisensteinInteger) -> EisensteinInteger {
+        EisensteinInteger {a: self.a + other.a, b: self.b + other.b}
+    }
+}
+
+impl Sub for EisensteinInteger {
+    type Output = EisensteinInteger;
+
+    fn sub(self, other: EisensteinInteger) -> EisensteinInteger {
+        self.difference(&other)
+    }
+}
+
+impl Mul for EisensteinInteger {
+    type Output = EisensteinInteger;
+
+    fn mul(self, other: EisensteinInteger) -> EisensteinInteger {
+        self.product(other)
+    }
+}
+
+impl Neg for EisensteinInteger {
+    type Output = EisensteinInteger;
+
+    fn neg(self) -> EisensteinInteger {
+        EisensteinInteger {a: -self.a, b: -self.b}
+    }
+}
+
+// Euclidean quotient, rounding to the nearest lattice point
+impl Div for EisensteinInteger {
+    type Output = EisensteinInteger;
+
+    fn div(self, other: EisensteinInteger) -> EisensteinInteger {
+        self.division(&other)
+    }
+}
+
+// Euclidean remainder, with norm strictly smaller than other's
+impl Rem for EisensteinInteger {
+    type Output = EisensteinInteger;
+
+    fn rem(self, other: EisensteinInteger) -> EisensteinInteger {
+        self - other * (self / other)
+    }
+}
+
 impl fmt::Display for EisensteinInteger {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.b == 0 {
@@ -316,7 +764,10 @@ impl fmt::Display for EisensteinInteger {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+// Always stored in lowest terms with a normalized denominator
+// (see EisensteinInteger::normalize), so the derived equality
+// and hash agree with equality in Q(w)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct QwElement {
     numer: EisensteinInteger,
     denom: EisensteinInteger
@@ -324,32 +775,59 @@ pub struct QwElement {
 
 impl QwElement {
     pub fn new(numer: EisensteinInteger, denom: EisensteinInteger) -> QwElement {
-        QwElement { numer, denom }
+        assert!(!denom.is_zero(), "QwElement with zero denominator");
+        if numer.is_zero() {
+            return QwElement { numer, denom: EisensteinInteger::one() };
+        }
+        let g = numer.gcd(&denom);
+        let (denom, u) = (denom / g).normalize();
+        QwElement { numer: (numer / g) * u, denom }
     }
-    // Evaluate the natural homomorphisms from Z[w]
-    // to F_p sending 1 to 1 and w to sqrt(-3) in F_p
-    // Returns None if either choice has bad reduction
-    pub fn reductions(&self, p: i64) -> (Option<i64>, Option<i64>) {
-        let (a1, a2) = self.numer.reductions(p);
-        let (b1, b2) = self.denom.reductions(p);
-
-        let r1 = {
-            if b1 == 0 {
-                None
-            } else {
-                Some((a1 * mod_inverse(b1, p)).rem_euclid(p))
-            }
-        };
 
-        let r2 = {
-            if b2 == 0 {
-                None
-            } else {
-                Some((a2 * mod_inverse(b2, p)).rem_euclid(p))
-            }
-        };
+    pub fn from_integer(a: EisensteinInteger) -> QwElement {
+        QwElement { numer: a, denom: EisensteinInteger::one() }
+    }
+
+    pub fn numer(&self) -> EisensteinInteger {
+        self.numer
+    }
+
+    pub fn denom(&self) -> EisensteinInteger {
+        self.denom
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer.is_zero()
+    }
 
-        (r1, r2)
+    pub fn inverse(&self) -> QwElement {
+        QwElement::new(self.denom, self.numer)
+    }
+
+    // The valuation at the prime pi, or None for 0
+    pub fn valuation(&self, pi: &EisensteinInteger) -> Option<i32> {
+        if self.is_zero() {
+            return None;
+        }
+        Some(self.numer.valuation(pi) as i32 - self.denom.valuation(pi) as i32)
+    }
+
+    // pi^k, for any integer k
+    pub fn prime_power(pi: EisensteinInteger, k: i32) -> QwElement {
+        let base = if k < 0 { QwElement::from_integer(pi).inverse() } else { QwElement::from_integer(pi) };
+        (0..k.abs()).fold(QwElement::one(), |acc, _| acc * base)
+    }
+
+    // Evaluate the natural homomorphisms from Z[w]
+    // to F_p (see EisensteinInteger::reductions)
+    // Each is None if that choice has bad reduction
+    pub fn reductions(&self, p: i64) -> Vec<Option<i64>> {
+        let numer = self.numer.reductions(p);
+        let denom = self.denom.reductions(p);
+        let m = Modulus::new(p);
+        numer.iter().zip(denom.iter()).map(|(&a, &b)| {
+            Some((m.int(a) * m.int(b).inverse()?).value())
+        }).collect()
     }
 
     pub fn one() -> QwElement {
@@ -360,10 +838,12 @@ impl QwElement {
         QwElement::new(EisensteinInteger::zero(), EisensteinInteger::one())
     }
     
+    #[allow(dead_code)]
     pub fn approx_coords(&self) -> Complex32 {
         self.numer.approx_coords() / self.denom.approx_coords()
     }
 
+    #[allow(dead_code)]
     pub fn phase_angle(&self) -> f32 {
         let raw = self.approx_coords().to_polar().1;
         if raw < 0.0 {
@@ -376,6 +856,46 @@ impl QwElement {
     }
 }
 
+impl Add for QwElement {
+    type Output = QwElement;
+
+    fn add(self, other: QwElement) -> QwElement {
+        QwElement::new(self.numer * other.denom + other.numer * self.denom, self.denom * other.denom)
+    }
+}
+
+impl Sub for QwElement {
+    type Output = QwElement;
+
+    fn sub(self, other: QwElement) -> QwElement {
+        self + (-other)
+    }
+}
+
+impl Mul for QwElement {
+    type Output = QwElement;
+
+    fn mul(self, other: QwElement) -> QwElement {
+        QwElement::new(self.numer * other.numer, self.denom * other.denom)
+    }
+}
+
+impl Neg for QwElement {
+    type Output = QwElement;
+
+    fn neg(self) -> QwElement {
+        QwElement { numer: -self.numer, denom: self.denom }
+    }
+}
+
+impl Div for QwElement {
+    type Output = QwElement;
+
+    fn div(self, other: QwElement) -> QwElement {
+        QwElement::new(self.numer * other.denom, self.denom * other.numer)
+    }
+}
+
 impl fmt::Display for QwElement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.denom == EisensteinInteger::one() {
@@ -399,59 +919,111 @@ impl PolynomialInQw {
         PolynomialInQw {coeffs}
     }
 
-    pub fn reductions(&self, p: i64) -> (Option<Polynomial>, Option<Polynomial>) {
-        let mut p1 = Vec::with_capacity(self.coeffs.len());
-        let mut p2 = Vec::with_capacity(self.coeffs.len());
+    #[allow(dead_code)]
+    pub fn eval(&self, x: QwElement) -> QwElement {
+        self.coeffs.iter().fold(QwElement::zero(), |acc, &c| acc * x + c)
+    }
 
-        let mut has_p1 = true;
-        let mut has_p2 = true;
+    pub fn degree(&self) -> usize {
+        self.coeffs.len() - 1
+    }
 
-        for c in &self.coeffs {
-            let (red1, red2) = c.reductions(p);
-            if has_p1 {
-                match red1 {
-                    None => {
-                        has_p1 = false;
-                        continue
-                    },
-                    Some(red1) => {
-                        p1.push(red1);
-                    }
-                }
-            }
-            if has_p2 {
-                match red2 {
-                    None => {
-                        has_p2 = false;
-                        continue
-                    },
-                    Some(red2) => {
-                        p2.push(red2);
-                    }
-                }
-            }
-            if !(has_p1 || has_p2) {
-                break;
+    // The conjugate L^-1 o f o L for L(z) = alpha z + beta, as
+    // PolynomialInQ::conjugate_affine
+    pub fn conjugate_affine(&self, alpha: QwElement, beta: QwElement) -> PolynomialInQw {
+        let mut res = vec![QwElement::zero()];
+        for &c in &self.coeffs {
+            let mut next = vec![QwElement::zero(); res.len() + 1];
+            for (i, &r) in res.iter().enumerate() {
+                next[i] = next[i] + r * alpha;
+                next[i + 1] = next[i + 1] + r * beta;
             }
+            let last = next.len() - 1;
+            next[last] = next[last] + c;
+            res = next;
         }
+        res.remove(0);
+        let last = res.len() - 1;
+        res[last] = res[last] - beta;
+        PolynomialInQw::from(res.into_iter().map(|c| c / alpha).collect())
+    }
 
-        let p1 = {
-            if !has_p1 {
-                None
-            } else {
-                Some(Polynomial::new(p1, Some(p)))
-            }
-        };
+    // Look for an affine conjugate of self over Q(w) with good
+    // reduction along the i-th homomorphism of qw_homomorphisms(p),
+    // returning it with the alpha and beta of conjugate_affine. The
+    // same argument as PolynomialInQ::good_reduction_model, with the
+    // prime pi of Z[w] in that homomorphism's kernel in place of p:
+    // alpha is a power of pi, and beta is the center of f or else runs
+    // over pi^-j Z[w] mod pi^k Z[w], whose classes are the sums of
+    // e pi^t for 0 <= e < p
+    pub fn good_reduction_model(&self, p: i64, i: usize) -> Option<(PolynomialInQw, QwElement, QwElement)> {
+        const MAX_MODEL_SHIFTS: i64 = 4096;
+        let d = self.degree();
+        if d < 2 {
+            return None;
+        }
+        let pi = EisensteinInteger::kernel_prime(p, qw_homomorphisms(p)[i]);
+        let good = |g: &PolynomialInQw| g.reductions(p)[i].is_some();
+        let lead = self.coeffs[0].valuation(&pi)?;
+        if lead % (d as i32 - 1) != 0 {
+            return None;
+        }
+        let k = -lead / (d as i32 - 1);
+        let alpha = QwElement::prime_power(pi, k);
 
-        let p2 = {
-            if !has_p2 {
-                None
-            } else {
-                Some(Polynomial::new(p2, Some(p)))
+        if !(d as i64).is_multiple_of(&p) {
+            let d_q = QwElement::from_integer(EisensteinInteger::new(d as i64, 0));
+            let beta = -self.coeffs[1] / (self.coeffs[0] * d_q);
+            let g = self.conjugate_affine(alpha, beta);
+            return if good(&g) { Some((g, alpha, beta)) } else { None };
+        }
+
+        let mut fixed = self.coeffs.clone();
+        fixed[d - 1] = fixed[d - 1] - QwElement::one();
+        let mut min_root = 0;
+        for (i, c) in fixed.iter().enumerate().skip(1) {
+            if let Some(v) = c.valuation(&pi) {
+                let slope = (v - lead) as f64 / i as f64;
+                min_root = min_root.min(slope.floor() as i32);
             }
-        };
+        }
+        let j = (-min_root).max(-k);
+        let digits = (j + k).max(0) as u32;
+        if digits > 0 && (p as f64).powi(digits as i32) > MAX_MODEL_SHIFTS as f64 {
+            return None;
+        }
+        let scale = QwElement::prime_power(pi, -j);
+        for b in 0..p.pow(digits) {
+            // b written in base p gives the digits e
+            let (mut rest, mut beta, mut pi_t) = (b, QwElement::zero(), QwElement::one());
+            for _ in 0..digits {
+                let e = QwElement::from_integer(EisensteinInteger::new(rest % p, 0));
+                beta = beta + e * pi_t;
+                pi_t = pi_t * QwElement::from_integer(pi);
+                rest /= p;
+            }
+            let beta = beta * scale;
+            let g = self.conjugate_affine(alpha, beta);
+            if good(&g) {
+                return Some((g, alpha, beta));
+            }
+        }
+        None
+    }
 
-        (p1, p2)
+    // The reductions of self along each of the
+    // homomorphisms Z[w] -> F_p, or None where self has bad
+    // reduction: some coefficient isn't integral there, or the
+    // leading coefficient reduces to 0
+    pub fn reductions(&self, p: i64) -> Vec<Option<Polynomial>> {
+        let coeffs: Vec<Vec<Option<i64>>> = self.coeffs.iter().map(|c| c.reductions(p)).collect();
+        (0..qw_homomorphisms(p).len()).map(|i| {
+            coeffs.iter()
+                .map(|c| c[i])
+                .collect::<Option<Vec<i64>>>()
+                .filter(|v| v[0] != 0)
+                .map(|v| Polynomial::new(v, Some(p)))
+        }).collect()
     }
 }
 
@@ -488,4 +1060,197 @@ mod tests {
         let b = EisensteinInteger::new(0, 1);
         println!("{:?}", a.division(&b));
     }
+
+    #[test]
+    fn resultants_and_discriminants() {
+        let q = |v: &[i64]| PolynomialInQ::from(v.iter().map(|&a| Rational::from_integer(a)).collect());
+        // res((x - 1)(x - 2), x - 3) = (1 - 3)(2 - 3)
+        assert_eq!(q(&[1, -3, 2]).resultant(&q(&[1, -3])), Rational::from_integer(2));
+        assert_eq!(q(&[1, -3]).resultant(&q(&[1, -3, 2])), Rational::from_integer(2));
+        assert_eq!(q(&[1, -3, 2]).resultant(&q(&[1, -1])), Rational::zero());
+        // x^2 - x + 1/4 = (x - 1/2)^2, and x^3 + ax + b has -4a^3 - 27b^2
+        let f = PolynomialInQ::from(vec![Rational::one(), -Rational::one(), Rational::new(1, 4)]);
+        assert_eq!(f.discriminant(), Rational::zero());
+        assert_eq!(q(&[1, 0, -2, 3]).discriminant(), Rational::from_integer(32 - 243));
+        // The same mod 101
+        let f = Polynomial::new(vec![1, 0, -2, 3], Some(101));
+        assert_eq!(f.discriminant(), (32i64 - 243).rem_euclid(101));
+        assert_eq!(Polynomial::new(vec![1, -3, 2], Some(101)).resultant(&Polynomial::new(vec![1, -3], Some(101))), 2);
+    }
+
+    #[test]
+    fn eisenstein_euclidean_ops() {
+        let a = EisensteinInteger::new(17, -5);
+        let b = EisensteinInteger::new(3, 4);
+        let (q, r) = (a / b, a % b);
+        assert_eq!(q * b + r, a);
+        assert!(r.norm_sq() < b.norm_sq());
+        assert_eq!(-a + a, EisensteinInteger::zero());
+
+        let (g, x, y) = a.extended_gcd(&b);
+        assert_eq!(x * a + y * b, g);
+        assert!(g.divides(&a) && g.divides(&b));
+
+        let c = EisensteinInteger::new(2, 1);
+        let (ga, gx, gy) = (a * c).extended_gcd(&(b * c));
+        assert_eq!(ga, c.normalize().0);
+        assert_eq!(gx * a * c + gy * b * c, ga);
+
+        // The six associates of c normalize to the same element
+        let mut u = EisensteinInteger::one();
+        for _ in 0..6 {
+            assert_eq!((c * u).normalize().0, c.normalize().0);
+            u = u * EisensteinInteger::new(1, 1);
+        }
+        assert!(EisensteinInteger::new(1, 1).is_unit());
+    }
+
+    #[test]
+    fn eisenstein_mod_inverse_and_factor() {
+        // 7 = (3 + w)(2 - w) splits, pi = 3 + w has residue field F_7
+        let pi = EisensteinInteger::new(3, 1);
+        for a in 1..7 {
+            let x = EisensteinInteger::new(a, 0);
+            let inv = x.mod_inverse(&pi).unwrap();
+            assert!(pi.divides(&(x * inv - EisensteinInteger::one())));
+        }
+        assert_eq!(pi.mod_inverse(&pi), None);
+
+        for &(a, b) in &[(84, 0), (-12, 30), (7, 3), (1, 1), (1, -1), (250, -91)] {
+            let z = EisensteinInteger::new(a, b);
+            let (u, fac) = z.factor();
+            assert!(u.is_unit());
+            let prod = fac.iter().fold(u, |acc, &(pi, e)| {
+                (0..e).fold(acc, |acc, _| acc * pi)
+            });
+            assert_eq!(prod, z);
+            for (pi, _) in fac {
+                assert_eq!(pi, pi.normalize().0);
+                assert!(!pi.is_unit());
+            }
+        }
+        // 3 is ramified: 3 = unit * (1 - w)^2
+        let (_, fac) = EisensteinInteger::new(3, 0).factor();
+        assert_eq!(fac.len(), 1);
+        assert_eq!(fac[0].1, 2);
+    }
+
+    #[test]
+    fn rational_normalized_arithmetic() {
+        assert_eq!(Rational::new(2, 2), Rational::one());
+        assert_eq!(Rational::new(6, -4), Rational::new(-3, 2));
+        assert_eq!(Rational::new(6, -4).denom, 2);
+        assert_eq!(Rational::new(0, -7), Rational::zero());
+
+        let x = Rational::new(-29, 16);
+        let y = Rational::new(5, 12);
+        assert_eq!(x + y, Rational::new(-67, 48));
+        assert_eq!((x - y) + y, x);
+        assert_eq!((x * y) / y, x);
+        assert_eq!(-x, Rational::new(29, 16));
+        assert_eq!(x.recip(), Rational::new(-16, 29));
+
+        assert!(x < y);
+        assert!(Rational::new(-913942, 1000000) > Rational::new(-913943, 1000000));
+        assert!(Rational::new(i64::MAX - 1, i64::MAX) < Rational::one());
+        let mut v = vec![y, x, Rational::zero(), Rational::new(-1, 3)];
+        v.sort();
+        assert_eq!(v, vec![x, Rational::new(-1, 3), Rational::zero(), y]);
+
+        assert_eq!(Rational::from(Rational64::new(10, -4)), Rational::new(-5, 2));
+        assert_eq!(Rational64::from(x), Rational64::new(-29, 16));
+
+        // z^2 - 29/16 has the 3-cycle -1/4 -> -7/4 -> 5/4
+        let f = PolynomialInQ::from(vec![Rational::one(), Rational::zero(), x]);
+        assert_eq!(f.eval(Rational::new(-1, 4)), Rational::new(-7, 4));
+        assert_eq!(f.eval(Rational::new(-7, 4)), Rational::new(5, 4));
+        assert_eq!(f.eval(Rational::new(5, 4)), Rational::new(-1, 4));
+    }
+
+    #[test]
+    fn good_reduction_models() {
+        let q = |a, b| Rational::new(a, b);
+        // 2x^2 + 1 drops degree mod 2
+        let f = PolynomialInQ::from(vec![q(2, 1), q(0, 1), q(1, 1)]);
+        assert!(!f.has_good_reduction(2));
+        assert!(f.has_good_reduction(3));
+
+        // z^2 + 1/4 is z^2 + z after z -> z + 1/2
+        let f = PolynomialInQ::from(vec![q(1, 1), q(0, 1), q(1, 4)]);
+        assert!(!f.has_good_reduction(2));
+        let (g, alpha, beta) = f.good_reduction_model(2).unwrap();
+        assert!(g.has_good_reduction(2));
+        for &x in &[q(3, 7), q(-5, 2)] {
+            // g = L^-1 o f o L
+            assert_eq!(alpha * g.eval(x) + beta, f.eval(alpha * x + beta));
+        }
+        // But z^2 - 29/16 has no good model at 2, nor z^2 + 1/9 at 3
+        let f = PolynomialInQ::from(vec![q(1, 1), q(0, 1), q(-29, 16)]);
+        assert!(f.good_reduction_model(2).is_none());
+        let f = PolynomialInQ::from(vec![q(1, 1), q(0, 1), q(1, 9)]);
+        assert!(f.good_reduction_model(3).is_none());
+
+        // 4x^2 + 2x conjugated by z -> z/4 is x^2 + 2x
+        let f = PolynomialInQ::from(vec![q(4, 1), q(2, 1), q(0, 1)]);
+        let (g, _, _) = f.good_reduction_model(2).unwrap();
+        assert!(g.has_good_reduction(2));
+        // 9x^2 at 3 would need v(alpha) = -2 for d - 1 = 1 ...
+        let f = PolynomialInQ::from(vec![q(9, 1), q(0, 1), q(1, 3)]);
+        assert!(f.good_reduction_model(3).is_some());
+        // ... while 3x^3 can't be made monic at 3
+        let f = PolynomialInQ::from(vec![q(3, 1), q(0, 1), q(0, 1), q(1, 1)]);
+        assert!(f.good_reduction_model(3).is_none());
+    }
+
+    #[test]
+    fn qw_normalized_arithmetic() {
+        use std::collections::HashSet;
+
+        let two = EisensteinInteger::new(2, 0);
+        let w = EisensteinInteger::new(0, 1);
+        assert_eq!(QwElement::new(two, two), QwElement::one());
+        // Multiplying through by a unit doesn't change the element
+        let x = QwElement::new(EisensteinInteger::new(5, 3), EisensteinInteger::new(2, 7));
+        let y = QwElement::new(EisensteinInteger::new(5, 3) * w, EisensteinInteger::new(2, 7) * w);
+        assert_eq!(x, y);
+        assert_eq!(QwElement::new(EisensteinInteger::zero(), two), QwElement::zero());
+
+        let set: HashSet<QwElement> = vec![x, y, x * QwElement::one()].into_iter().collect();
+        assert_eq!(set.len(), 1);
+
+        let z = QwElement::new(EisensteinInteger::new(-1, 4), EisensteinInteger::new(3, 0));
+        assert_eq!((x + z) - z, x);
+        assert_eq!((x * z) / z, x);
+        assert_eq!(x / x, QwElement::one());
+        assert_eq!(x - x, QwElement::zero());
+
+        // z^2 + x at z
+        let f = PolynomialInQw::from(vec![QwElement::one(), QwElement::zero(), x]);
+        assert_eq!(f.eval(z), z * z + x);
+    }
+
+    #[test]
+    fn qw_good_reduction_models() {
+        let int = |a, b| QwElement::from_integer(EisensteinInteger::new(a, b));
+        let g = PolynomialInQw::from(vec![int(1, 0), int(0, 1), int(1, 0)]);
+        // Conjugating z^2 + wz + 1 by z -> pi z, for a prime pi over 7,
+        // gives pi z^2 + wz + 1/pi: bad reduction along pi only
+        let pi = EisensteinInteger::kernel_prime(7, qw_homomorphisms(7)[0]);
+        let f = g.conjugate_affine(QwElement::from_integer(pi), QwElement::zero());
+        let reds = f.reductions(7);
+        assert!(reds[0].is_none() && reds[1].is_some());
+        let (h, alpha, beta) = f.good_reduction_model(7, 0).unwrap();
+        assert!(h.reductions(7)[0].is_some());
+        for &x in &[int(2, -1), QwElement::new(EisensteinInteger::new(1, 1), EisensteinInteger::new(5, 0))] {
+            assert_eq!(alpha * h.eval(x) + beta, f.eval(alpha * x + beta));
+        }
+
+        // 3 divides the degree of z^3 + wz + 1, so undoing a shift by
+        // 1/(1 - w) takes the search over the classes of beta
+        let g = PolynomialInQw::from(vec![int(1, 0), int(0, 0), int(0, 1), int(1, 0)]);
+        let f = g.conjugate_affine(QwElement::one(), int(1, -1).inverse());
+        assert!(f.reductions(3)[0].is_none());
+        let (h, _, _) = f.good_reduction_model(3, 0).unwrap();
+        assert!(h.reductions(3)[0].is_some());
+    }
 }
\ No newline at end of file